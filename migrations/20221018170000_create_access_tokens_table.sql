@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS access_tokens (
+    id serial primary key,
+    owner text not null references users (username) on delete cascade,
+    name text not null,
+    hash text not null,
+    path text default null,
+    action action default null,
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz default null,
+    expires_at timestamptz default null,
+    unique (owner, name)
+);
+
+-- Carry over existing tokens so connected clients keep working
+INSERT INTO access_tokens (owner, name, hash)
+SELECT username, 'default', access_token FROM users WHERE access_token IS NOT NULL;
+
+ALTER TABLE users DROP COLUMN access_token;
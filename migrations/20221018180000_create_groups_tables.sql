@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS groups (
+    name text not null primary key,
+    description text not null default ''
+);
+
+CREATE TABLE IF NOT EXISTS group_members (
+    group_name text not null references groups (name) on delete cascade,
+    username text not null references users (username) on delete cascade,
+    primary key (group_name, username)
+);
+
+-- Permissions can now be applied to either a user or a group
+ALTER TABLE permissions ALTER COLUMN applies_to DROP NOT NULL;
+ALTER TABLE permissions ADD COLUMN applies_to_group text default null references groups (name) on delete cascade;
+ALTER TABLE permissions ADD CONSTRAINT permissions_single_target CHECK ((applies_to IS NULL) <> (applies_to_group IS NULL));
+ALTER TABLE permissions ADD CONSTRAINT permissions_group_unique UNIQUE (applies_to_group, path, action, affects_children);
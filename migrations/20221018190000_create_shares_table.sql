@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS shares (
+    id serial primary key,
+    slug text not null unique,
+    owner text not null references users (username) on delete cascade,
+    path text not null,
+    password text default null,
+    expires_at timestamptz default null,
+    max_downloads integer default null,
+    downloads integer not null default 0,
+    created_at timestamptz not null default now()
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS locks (
+    token text not null primary key,
+    path text not null,
+    href text not null,
+    principal text default null references users (username) on delete cascade,
+    owner text default null,
+    shared boolean not null,
+    deep boolean not null,
+    timeout_secs bigint default null,
+    timeout_at timestamptz default null,
+    created_at timestamptz not null default now()
+);
+
+CREATE INDEX IF NOT EXISTS locks_path_idx ON locks (path);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS dav_properties (
+    path text not null,
+    namespace text not null default '',
+    name text not null,
+    prefix text default null,
+    xml bytea default null,
+    primary key (path, namespace, name)
+);
@@ -0,0 +1,14 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS quota bigint default null;
+
+CREATE TABLE IF NOT EXISTS folder_quotas (
+    folder text not null primary key,
+    quota bigint not null
+);
+
+CREATE TABLE IF NOT EXISTS file_usage (
+    path text not null primary key,
+    owner text not null references users (username) on delete cascade,
+    size bigint not null
+);
+
+CREATE INDEX IF NOT EXISTS file_usage_owner_idx ON file_usage (owner);
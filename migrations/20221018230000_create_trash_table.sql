@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS trash (
+    id serial primary key,
+    original_path text not null,
+    name text not null unique,
+    is_directory boolean not null,
+    deleted_by text default null references users (username) on delete set null,
+    deleted_at timestamptz not null default now()
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS file_versions (
+    id serial primary key,
+    path text not null,
+    name text not null unique,
+    size bigint not null,
+    last_modified timestamptz not null,
+    created_by text default null references users (username) on delete set null,
+    created_at timestamptz not null default now()
+);
+
+CREATE INDEX IF NOT EXISTS file_versions_path_idx ON file_versions (path);
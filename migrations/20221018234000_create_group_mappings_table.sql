@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS group_mappings (
+    external_group text not null primary key,
+    default_access action default null,
+    group_name text default null references groups (name) on delete set null
+);
+
+-- Track what was granted through group mappings, so it can be revoked when the external groups change
+ALTER TABLE group_members ADD COLUMN IF NOT EXISTS managed boolean not null default false;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS managed_access boolean not null default false;
@@ -0,0 +1,7 @@
+-- Permissions can match a glob pattern rather than an exact path
+ALTER TABLE permissions ADD COLUMN IF NOT EXISTS pattern boolean not null default false;
+
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_applies_to_path_action_affects_children_key;
+ALTER TABLE permissions ADD CONSTRAINT permissions_user_unique UNIQUE (applies_to, path, pattern, action, affects_children);
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_group_unique;
+ALTER TABLE permissions ADD CONSTRAINT permissions_group_unique UNIQUE (applies_to_group, path, pattern, action, affects_children);
@@ -0,0 +1,2 @@
+-- Permissions can grant a custom set of capabilities instead of those from their action
+ALTER TABLE permissions ADD COLUMN IF NOT EXISTS custom_capabilities integer default null;
@@ -0,0 +1,3 @@
+-- Permissions are removed along with the user they apply to
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_applies_to_fkey;
+ALTER TABLE permissions ADD CONSTRAINT permissions_applies_to_fkey FOREIGN KEY (applies_to) REFERENCES users (username) ON DELETE CASCADE;
@@ -0,0 +1,4 @@
+-- Permissions can be limited to a window of time
+ALTER TABLE permissions ADD COLUMN IF NOT EXISTS valid_from timestamptz default null;
+ALTER TABLE permissions ADD COLUMN IF NOT EXISTS valid_until timestamptz default null;
+ALTER TABLE permissions ADD CONSTRAINT permissions_valid_window CHECK (valid_from IS NULL OR valid_until IS NULL OR valid_from < valid_until);
@@ -0,0 +1,37 @@
+-- Each user or group has at most one permission for a path, so assigning it again replaces the
+-- existing one. Only the most recent of any duplicates is kept, the others are moved to
+-- discarded_permissions so they can be reviewed and restored by hand.
+CREATE TABLE discarded_permissions (
+    LIKE permissions,
+    discarded_at timestamptz NOT NULL DEFAULT now(),
+    reason text NOT NULL
+);
+
+DO $$
+DECLARE
+    discarded bigint;
+BEGIN
+    WITH duplicates AS (
+        DELETE FROM permissions a USING permissions b
+        WHERE a.id < b.id
+            AND a.applies_to IS NOT DISTINCT FROM b.applies_to
+            AND a.applies_to_group IS NOT DISTINCT FROM b.applies_to_group
+            AND a.path = b.path
+            AND a.pattern = b.pattern
+            AND a.affects_children = b.affects_children
+        RETURNING a.*
+    )
+    INSERT INTO discarded_permissions
+    SELECT duplicates.*, now(), 'duplicate of a more recent permission for the same path'
+    FROM duplicates;
+
+    GET DIAGNOSTICS discarded = ROW_COUNT;
+    IF discarded > 0 THEN
+        RAISE WARNING '% duplicate permission(s) were removed, see the discarded_permissions table', discarded;
+    END IF;
+END $$;
+
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_user_unique;
+ALTER TABLE permissions ADD CONSTRAINT permissions_user_unique UNIQUE (applies_to, path, pattern, affects_children);
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_group_unique;
+ALTER TABLE permissions ADD CONSTRAINT permissions_group_unique UNIQUE (applies_to_group, path, pattern, affects_children);
@@ -0,0 +1 @@
+ALTER TABLE folder_quotas ADD COLUMN IF NOT EXISTS used bigint not null default 0;
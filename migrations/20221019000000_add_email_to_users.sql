@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email text default null;
+
+-- New users follow the group mappings until an admin assigns their access
+ALTER TABLE users ALTER COLUMN managed_access SET DEFAULT true;
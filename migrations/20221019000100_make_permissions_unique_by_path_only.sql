@@ -0,0 +1,30 @@
+-- Whether a permission affects children is part of what it grants rather than what it applies to,
+-- so a user or group has at most one permission for a path either way. Only the most recent of
+-- any duplicates is kept, the others are moved to discarded_permissions.
+DO $$
+DECLARE
+    discarded bigint;
+BEGIN
+    WITH duplicates AS (
+        DELETE FROM permissions a USING permissions b
+        WHERE a.id < b.id
+            AND a.applies_to IS NOT DISTINCT FROM b.applies_to
+            AND a.applies_to_group IS NOT DISTINCT FROM b.applies_to_group
+            AND a.path = b.path
+            AND a.pattern = b.pattern
+        RETURNING a.*
+    )
+    INSERT INTO discarded_permissions
+    SELECT duplicates.*, now(), 'duplicate of a more recent permission for the same path, regardless of whether it affects children'
+    FROM duplicates;
+
+    GET DIAGNOSTICS discarded = ROW_COUNT;
+    IF discarded > 0 THEN
+        RAISE WARNING '% duplicate permission(s) were removed, see the discarded_permissions table', discarded;
+    END IF;
+END $$;
+
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_user_unique;
+ALTER TABLE permissions ADD CONSTRAINT permissions_user_unique UNIQUE (applies_to, path, pattern);
+ALTER TABLE permissions DROP CONSTRAINT IF EXISTS permissions_group_unique;
+ALTER TABLE permissions ADD CONSTRAINT permissions_group_unique UNIQUE (applies_to_group, path, pattern);
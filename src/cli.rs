@@ -0,0 +1,675 @@
+use crate::{
+    config::Config,
+    database::{self, Action, Capability, Group, Permission, User},
+    export::{self, Document, Format},
+    security::{new_permission, normalize_path},
+};
+use clap::{Args, Parser, Subcommand};
+use eyre::{eyre, WrapErr};
+use sqlx::PgPool;
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// A simple WebDAV server with a basic web UI, authentication, and permissions
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the server (default)
+    Serve,
+    /// Run any pending database migrations
+    Migrate,
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage access tokens
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+    /// Manage permissions
+    Permission {
+        #[command(subcommand)]
+        command: PermissionCommand,
+    },
+    /// Export all users, groups and permissions
+    Export {
+        /// Where to write the document, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// The format to write, defaults to the output's extension or JSON
+        #[arg(short, long)]
+        format: Option<Format>,
+    },
+    /// Import users, groups and permissions from an exported document
+    Import {
+        /// The document to import, or - for stdin
+        input: PathBuf,
+        /// The format to read, defaults to the input's extension or JSON
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// Only report the changes that would be made
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Create a new user
+    Create {
+        username: String,
+        /// The user's display name, defaults to their username
+        #[arg(long)]
+        name: Option<String>,
+        /// The access the user has to paths without permissions
+        #[arg(short, long)]
+        access: Option<Action>,
+    },
+    /// List all the users
+    List,
+    /// Change the access a user has to paths without permissions
+    Promote {
+        username: String,
+        /// The access to grant
+        #[arg(short, long, default_value = "admin")]
+        access: Action,
+    },
+    /// Delete a user along with their permissions and access tokens
+    Delete {
+        username: String,
+        /// Another user to hand the deleted user's shares, file usage, trash and versions to
+        #[arg(long)]
+        reassign_to: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+    /// Issue an access token for a user and print it
+    Issue {
+        username: String,
+        /// The name of a new token, otherwise the user's default token is regenerated
+        #[arg(long)]
+        name: Option<String>,
+        /// Limit the token to a path
+        #[arg(long, requires = "name")]
+        path: Option<String>,
+        /// Limit the token to an action
+        #[arg(long, requires = "name")]
+        action: Option<Action>,
+        /// When the token expires, as an RFC 3339 timestamp
+        #[arg(long, requires = "name", value_parser = parse_timestamp)]
+        expires_at: Option<OffsetDateTime>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PermissionCommand {
+    /// List the permissions assigned to a user or group
+    List {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Grant a permission to a user or group, replacing any existing one for the same path
+    Grant {
+        #[command(flatten)]
+        target: Target,
+        /// The path, or glob pattern, the permission applies to
+        path: String,
+        action: Action,
+        /// Treat the path as a glob pattern
+        #[arg(long)]
+        pattern: bool,
+        /// Apply the permission to everything within the path as well
+        #[arg(long)]
+        children: bool,
+        /// Grant these capabilities instead of those from the action
+        #[arg(long, value_delimiter = ',')]
+        capabilities: Option<Vec<Capability>>,
+        /// When the permission starts applying, as an RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        valid_from: Option<OffsetDateTime>,
+        /// When the permission stops applying, as an RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        valid_until: Option<OffsetDateTime>,
+    },
+    /// Revoke a permission by its ID
+    Revoke { id: i32 },
+}
+
+/// The user or group a permission applies to
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct Target {
+    #[arg(long)]
+    user: Option<String>,
+    #[arg(long)]
+    group: Option<String>,
+}
+
+/// Run an administrative command
+pub async fn run(command: Command, config: Arc<Config>) -> eyre::Result<()> {
+    let db = database::connect(&config.database_url).await?;
+
+    match command {
+        Command::Serve => unreachable!("the server is started separately"),
+        // Migrations are run whenever the database is connected to
+        Command::Migrate => println!("database schema up-to-date"),
+        Command::User { command } => run_user(&db, command).await?,
+        Command::Token { command } => run_token(&db, command).await?,
+        Command::Permission { command } => run_permission(&db, command).await?,
+        Command::Export { output, format } => {
+            let format = format.unwrap_or_else(|| detect_format(output.as_deref()));
+            let document = export::export(&db).await?;
+            let serialized = document.to_string(format).map_err(|e| eyre!(e))?;
+
+            match output {
+                Some(path) => fs::write(&path, serialized)
+                    .wrap_err_with(|| format!("failed to write {}", path.display()))?,
+                None => println!("{serialized}"),
+            }
+        }
+        Command::Import {
+            input,
+            format,
+            dry_run,
+        } => {
+            let raw = match input.to_str() {
+                Some("-") => {
+                    let mut raw = String::new();
+                    io::stdin().read_to_string(&mut raw)?;
+                    raw
+                }
+                _ => fs::read_to_string(&input)
+                    .wrap_err_with(|| format!("failed to read {}", input.display()))?,
+            };
+            let format = format.unwrap_or_else(|| detect_format(Some(&input)));
+            let document =
+                Document::parse(&raw, format).map_err(|e| eyre!("invalid document: {e}"))?;
+
+            let report = export::import(&db, &document, dry_run).await?;
+            for change in &report.changes {
+                println!("{} {}", change.kind, change.description);
+            }
+
+            match (report.changes.len(), dry_run) {
+                (0, _) => println!("already up-to-date"),
+                (count, true) => println!("{count} change(s) would be made"),
+                (count, false) => println!("{count} change(s) made"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a user management command
+async fn run_user(db: &PgPool, command: UserCommand) -> eyre::Result<()> {
+    match command {
+        UserCommand::Create {
+            username,
+            name,
+            access,
+        } => {
+            if username.trim().is_empty() {
+                return Err(eyre!("username cannot be empty"));
+            } else if User::get(db, &username).await?.is_some() {
+                return Err(eyre!("user {username} already exists"));
+            }
+
+            let name = name.as_deref().unwrap_or(&username);
+            let mut user = User::create_if_not_exists(db, &username, name).await?;
+            if let Some(access) = access {
+                user.set_default_action(db, access).await?;
+            }
+
+            println!(
+                "created user {} with {:?} access",
+                user.username, user.default_access
+            );
+        }
+        UserCommand::List => {
+            let mut users = User::list(db).await?;
+            users.sort_by(|a, b| a.username.cmp(&b.username));
+            for user in users {
+                let quota = match user.quota {
+                    Some(quota) => format!("{quota} bytes"),
+                    None => String::from("unlimited"),
+                };
+                println!(
+                    "{}\t{}\t{:?}\t{quota}",
+                    user.username, user.name, user.default_access
+                );
+            }
+        }
+        UserCommand::Promote { username, access } => {
+            let mut user = find_user(db, &username).await?;
+            user.set_default_action(db, access).await?;
+
+            println!("user {username} now has {access:?} access");
+        }
+        UserCommand::Delete {
+            username,
+            reassign_to,
+        } => {
+            find_user(db, &username).await?;
+            if let Some(new_owner) = &reassign_to {
+                if *new_owner == username {
+                    return Err(eyre!("cannot reassign to the user being deleted"));
+                } else if User::get(db, new_owner).await?.is_none() {
+                    return Err(eyre!("user to reassign to does not exist"));
+                }
+            }
+
+            let deletion = User::delete(db, &username, reassign_to.as_deref()).await?;
+            println!(
+                "deleted user {username} along with {} permission(s), {} access token(s), \
+                {} share(s), {} lock(s) and {} group membership(s)",
+                deletion.permissions,
+                deletion.access_tokens,
+                deletion.shares,
+                deletion.locks,
+                deletion.group_memberships
+            );
+            if let Some(new_owner) = reassign_to {
+                println!(
+                    "reassigned {} share(s) and the usage of {} file(s) to {new_owner}",
+                    deletion.reassigned_shares, deletion.reassigned_usage
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run an access token management command
+async fn run_token(db: &PgPool, command: TokenCommand) -> eyre::Result<()> {
+    match command {
+        TokenCommand::Issue {
+            username,
+            name,
+            path,
+            action,
+            expires_at,
+        } => {
+            let user = find_user(db, &username).await?;
+
+            let token = match name.as_deref().map(str::trim) {
+                None => user.regenerate_access_token(db).await?,
+                Some("") => return Err(eyre!("name cannot be empty")),
+                Some(name) => {
+                    if matches!(expires_at, Some(e) if e <= OffsetDateTime::now_utc()) {
+                        return Err(eyre!("expiry must be in the future"));
+                    }
+
+                    let path = path.as_deref().map(normalize_path);
+                    let (_, token) = user
+                        .create_access_token(db, name, path.as_deref(), action, expires_at)
+                        .await?
+                        .ok_or_else(|| eyre!("an access token with that name already exists"))?;
+                    token
+                }
+            };
+
+            println!("{token}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a permission management command
+async fn run_permission(db: &PgPool, command: PermissionCommand) -> eyre::Result<()> {
+    match command {
+        PermissionCommand::List { target } => {
+            let mut permissions = match (target.user, target.group) {
+                (Some(username), _) => find_user(db, &username).await?.permissions(db).await?,
+                (_, Some(name)) => find_group(db, &name).await?.permissions(db).await?,
+                (None, None) => unreachable!("clap requires a target"),
+            };
+            permissions.sort_by(|a, b| a.path.cmp(&b.path).then(a.id.cmp(&b.id)));
+
+            for permission in permissions {
+                let capabilities = permission
+                    .capabilities()
+                    .iter()
+                    .map(|capability| format!("{capability:?}").to_lowercase())
+                    .collect::<Vec<_>>();
+
+                let mut flags = Vec::new();
+                if permission.pattern {
+                    flags.push(String::from("pattern"));
+                }
+                if permission.affects_children {
+                    flags.push(String::from("children"));
+                }
+                if let Some(from) = permission.valid_from {
+                    flags.push(format!("from {}", from.format(&Rfc3339)?));
+                }
+                if let Some(until) = permission.valid_until {
+                    flags.push(format!("until {}", until.format(&Rfc3339)?));
+                }
+
+                println!(
+                    "{}\t{}\t{:?}\t{}\t{}",
+                    permission.id,
+                    permission.path,
+                    permission.action,
+                    capabilities.join(","),
+                    flags.join(", ")
+                );
+            }
+        }
+        PermissionCommand::Grant {
+            target,
+            path,
+            action,
+            pattern,
+            children,
+            capabilities,
+            valid_from,
+            valid_until,
+        } => {
+            let permission = new_permission(
+                &path,
+                pattern,
+                action,
+                capabilities,
+                children,
+                valid_from,
+                valid_until,
+            )
+            .map_err(|e| eyre!(e))?;
+            let granted = match (target.user, target.group) {
+                (Some(username), _) => {
+                    let user = find_user(db, &username).await?;
+                    user.assign_permission(db, &permission).await?
+                }
+                (_, Some(name)) => {
+                    let group = find_group(db, &name).await?;
+                    group.assign_permission(db, &permission).await?
+                }
+                (None, None) => unreachable!("clap requires a target"),
+            };
+
+            println!("granted permission {}", granted.id);
+        }
+        PermissionCommand::Revoke { id } => {
+            if Permission::get(db, id).await?.is_none() {
+                return Err(eyre!("permission {id} does not exist"));
+            }
+
+            Permission::delete(db, id).await?;
+            println!("revoked permission {id}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Find a user that must exist
+async fn find_user(db: &PgPool, username: &str) -> eyre::Result<User> {
+    User::get(db, username)
+        .await?
+        .ok_or_else(|| eyre!("user {username} does not exist"))
+}
+
+/// Find a group that must exist
+async fn find_group(db: &PgPool, name: &str) -> eyre::Result<Group> {
+    Group::get(db, name)
+        .await?
+        .ok_or_else(|| eyre!("group {name} does not exist"))
+}
+
+/// Parse an RFC 3339 timestamp from an argument
+fn parse_timestamp(raw: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(raw, &Rfc3339).map_err(|e| e.to_string())
+}
+
+/// Guess the format of a document from its extension
+fn detect_format(path: Option<&Path>) -> Format {
+    match path.and_then(Path::extension).and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => Format::Yaml,
+        _ => Format::Json,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Command, PermissionCommand, UserCommand};
+    use crate::database::{Action, Capability};
+    use clap::{error::ErrorKind, CommandFactory, Parser};
+
+    fn parse(args: &[&str]) -> Command {
+        let cli = Cli::try_parse_from([&["davoxide"], args].concat()).unwrap();
+        cli.command.unwrap()
+    }
+
+    fn parse_error(args: &[&str]) -> ErrorKind {
+        Cli::try_parse_from([&["davoxide"], args].concat())
+            .unwrap_err()
+            .kind()
+    }
+
+    fn parse_user(args: &[&str]) -> UserCommand {
+        match parse(&[&["user"], args].concat()) {
+            Command::User { command } => command,
+            command => panic!("parsed as {command:?}"),
+        }
+    }
+
+    fn parse_permission(args: &[&str]) -> PermissionCommand {
+        match parse(&[&["permission"], args].concat()) {
+            Command::Permission { command } => command,
+            command => panic!("parsed as {command:?}"),
+        }
+    }
+
+    #[test]
+    fn valid_arguments() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn serves_by_default() {
+        let cli = Cli::try_parse_from(["davoxide"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn user_create() {
+        let command = parse_user(&["create", "alice", "--name", "Alice", "--access", "admin"]);
+        assert!(matches!(
+            command,
+            UserCommand::Create { username, name: Some(name), access: Some(Action::Admin) }
+                if username == "alice" && name == "Alice"
+        ));
+
+        let command = parse_user(&["create", "bob"]);
+        assert!(matches!(
+            command,
+            UserCommand::Create { username, name: None, access: None } if username == "bob"
+        ));
+
+        assert_eq!(
+            parse_error(&["user", "create", "carol", "--access", "owner"]),
+            ErrorKind::InvalidValue
+        );
+        assert_eq!(
+            parse_error(&["user", "create"]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn user_promote() {
+        let command = parse_user(&["promote", "alice"]);
+        assert!(matches!(
+            command,
+            UserCommand::Promote { username, access: Action::Admin } if username == "alice"
+        ));
+
+        let command = parse_user(&["promote", "alice", "-a", "read"]);
+        assert!(matches!(
+            command,
+            UserCommand::Promote {
+                access: Action::Read,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn user_delete() {
+        let command = parse_user(&["delete", "alice"]);
+        assert!(matches!(
+            command,
+            UserCommand::Delete { username, reassign_to: None } if username == "alice"
+        ));
+
+        let command = parse_user(&["delete", "alice", "--reassign-to", "bob"]);
+        assert!(matches!(
+            command,
+            UserCommand::Delete { reassign_to: Some(new_owner), .. } if new_owner == "bob"
+        ));
+    }
+
+    #[test]
+    fn permission_grant() {
+        let command = parse_permission(&[
+            "grant",
+            "--group",
+            "contractors",
+            "**/*.key",
+            "deny",
+            "--pattern",
+        ]);
+        let PermissionCommand::Grant {
+            target,
+            path,
+            action,
+            pattern,
+            children,
+            capabilities,
+            valid_from,
+            valid_until,
+        } = command
+        else {
+            panic!("parsed as {command:?}");
+        };
+        assert_eq!(target.user, None);
+        assert_eq!(target.group.as_deref(), Some("contractors"));
+        assert_eq!(path, "**/*.key");
+        assert_eq!(action, Action::Deny);
+        assert!(pattern);
+        assert!(!children);
+        assert_eq!(capabilities, None);
+        assert_eq!(valid_from, None);
+        assert_eq!(valid_until, None);
+    }
+
+    #[test]
+    fn permission_grant_capabilities_and_validity() {
+        let command = parse_permission(&[
+            "grant",
+            "--user",
+            "bob",
+            "/inbox",
+            "deny",
+            "--children",
+            "--capabilities",
+            "create,list",
+            "--valid-until",
+            "2030-01-01T00:00:00Z",
+        ]);
+        let PermissionCommand::Grant {
+            target,
+            children,
+            capabilities,
+            valid_from,
+            valid_until,
+            ..
+        } = command
+        else {
+            panic!("parsed as {command:?}");
+        };
+        assert_eq!(target.user.as_deref(), Some("bob"));
+        assert!(children);
+        assert_eq!(
+            capabilities,
+            Some(vec![Capability::Create, Capability::List])
+        );
+        assert_eq!(valid_from, None);
+        assert_eq!(valid_until.unwrap().year(), 2030);
+
+        assert_eq!(
+            parse_error(&[
+                "permission",
+                "grant",
+                "--user",
+                "bob",
+                "/",
+                "read",
+                "--valid-from",
+                "tomorrow"
+            ]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            parse_error(&[
+                "permission",
+                "grant",
+                "--user",
+                "bob",
+                "/",
+                "read",
+                "--capabilities",
+                "read,fly"
+            ]),
+            ErrorKind::InvalidValue
+        );
+    }
+
+    #[test]
+    fn permission_target() {
+        let command = parse_permission(&["list", "--user", "bob"]);
+        assert!(matches!(
+            command,
+            PermissionCommand::List { target } if target.user.as_deref() == Some("bob")
+        ));
+
+        assert_eq!(
+            parse_error(&["permission", "list"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            parse_error(&["permission", "list", "--user", "bob", "--group", "staff"]),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn permission_revoke() {
+        let command = parse_permission(&["revoke", "42"]);
+        assert!(matches!(command, PermissionCommand::Revoke { id: 42 }));
+
+        assert_eq!(
+            parse_error(&["permission", "revoke", "latest"]),
+            ErrorKind::ValueValidation
+        );
+    }
+}
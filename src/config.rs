@@ -1,7 +1,15 @@
 use eyre::WrapErr;
+use ipnet::{IpNet, Ipv4Net};
+use jsonwebtoken::Algorithm;
 use std::env::current_dir;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::{env, net::SocketAddr, path::PathBuf};
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+use time::Duration;
 
 pub struct Config {
     /// The address the server should listen on
@@ -10,6 +18,50 @@ pub struct Config {
     pub database_url: String,
     /// The path files should be served from
     pub path: PathBuf,
+    /// How long deleted items are kept in the trash before being permanently removed
+    pub trash_retention: Duration,
+    /// The most previous versions to keep for each file
+    pub version_limit: usize,
+    /// How long previous versions of files are kept
+    pub version_retention: Duration,
+    /// The networks SSO proxy headers are accepted from
+    pub trusted_proxies: Vec<IpNet>,
+    /// The secret the SSO proxy must send alongside its headers, if any
+    pub sso_secret: Option<String>,
+    /// How many consecutive login attempts can fail before being locked out
+    pub login_attempt_limit: u32,
+    /// How long the first lockout lasts, doubling with each further failure
+    pub lockout_duration: Duration,
+    /// The OpenID Connect provider used to login to the web UI, if any
+    pub oidc: Option<OidcConfig>,
+}
+
+#[derive(Clone)]
+pub struct OidcConfig {
+    /// The URL of the issuer, used to discover the provider's endpoints
+    pub issuer: String,
+    /// The ID of the client registered with the provider
+    pub client_id: String,
+    /// The secret of the client registered with the provider
+    pub client_secret: Option<String>,
+    /// The URL the provider redirects back to after logging in
+    pub redirect_url: String,
+    /// The scopes to request
+    pub scopes: String,
+    /// The claim containing the username
+    pub username_claim: String,
+    /// The claim containing the display name
+    pub name_claim: String,
+    /// The claim containing the user's groups
+    pub groups_claim: String,
+    /// The claim containing the user's email address
+    pub email_claim: String,
+    /// The algorithms ID tokens may be signed with, or empty to use those the provider advertises
+    pub algorithms: Vec<Algorithm>,
+    /// The secret used to sign session cookies
+    pub session_secret: Option<String>,
+    /// How long a login session lasts
+    pub session_lifetime: Duration,
 }
 
 /// Parse the configuration from the database
@@ -30,10 +82,185 @@ pub fn load() -> eyre::Result<Arc<Config>> {
         .unwrap_or(current_dir)
         .canonicalize()?;
 
+    let trash_retention_days = env::var("TRASH_RETENTION_DAYS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse()
+        .wrap_err("invalid trash retention period")?;
+    let version_limit = env::var("VERSION_LIMIT")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse()
+        .wrap_err("invalid version limit")?;
+    let version_retention_days = env::var("VERSION_RETENTION_DAYS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse()
+        .wrap_err("invalid version retention period")?;
+
+    let trusted_proxies = env::var("TRUSTED_PROXIES")
+        .unwrap_or_else(|_| String::from("127.0.0.0/8,::1/128"))
+        .split(',')
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .map(parse_network)
+        .collect::<Result<_, _>>()
+        .wrap_err("invalid trusted proxy format")?;
+    let sso_secret = env::var("SSO_SECRET").ok().filter(|s| !s.is_empty());
+
+    let login_attempt_limit = env::var("LOGIN_ATTEMPT_LIMIT")
+        .unwrap_or_else(|_| String::from("5"))
+        .parse()
+        .wrap_err("invalid login attempt limit")?;
+    let lockout_seconds = env::var("LOCKOUT_SECONDS")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse()
+        .wrap_err("invalid lockout duration")?;
+
+    let oidc = match env::var("OIDC_ISSUER") {
+        Ok(issuer) => Some(load_oidc(issuer)?),
+        Err(_) => None,
+    };
+
     let config = Config {
         address,
         database_url,
         path,
+        trash_retention: Duration::days(trash_retention_days),
+        version_limit,
+        version_retention: Duration::days(version_retention_days),
+        trusted_proxies,
+        sso_secret,
+        login_attempt_limit,
+        lockout_duration: Duration::seconds(lockout_seconds),
+        oidc,
     };
     Ok(Arc::new(config))
 }
+
+/// Parse the OpenID Connect configuration for the issuer
+fn load_oidc(issuer: String) -> eyre::Result<OidcConfig> {
+    let client_id = env::var("OIDC_CLIENT_ID").wrap_err("missing OIDC_CLIENT_ID in environment")?;
+    let client_secret = env::var("OIDC_CLIENT_SECRET").ok();
+    let redirect_url =
+        env::var("OIDC_REDIRECT_URL").wrap_err("missing OIDC_REDIRECT_URL in environment")?;
+
+    let scopes = env::var("OIDC_SCOPES").unwrap_or_else(|_| String::from("openid profile"));
+    let username_claim =
+        env::var("OIDC_USERNAME_CLAIM").unwrap_or_else(|_| String::from("preferred_username"));
+    let name_claim = env::var("OIDC_NAME_CLAIM").unwrap_or_else(|_| String::from("name"));
+    let groups_claim = env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| String::from("groups"));
+    let email_claim = env::var("OIDC_EMAIL_CLAIM").unwrap_or_else(|_| String::from("email"));
+    let algorithms = env::var("OIDC_ID_TOKEN_ALGORITHMS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|algorithm| !algorithm.is_empty())
+        .map(Algorithm::from_str)
+        .collect::<Result<_, _>>()
+        .wrap_err("invalid ID token algorithm")?;
+
+    let session_secret = env::var("SESSION_SECRET").ok().filter(|s| !s.is_empty());
+    let session_lifetime_hours = env::var("SESSION_LIFETIME_HOURS")
+        .unwrap_or_else(|_| String::from("24"))
+        .parse()
+        .wrap_err("invalid session lifetime")?;
+
+    Ok(OidcConfig {
+        issuer,
+        client_id,
+        client_secret,
+        redirect_url,
+        scopes,
+        username_claim,
+        name_claim,
+        groups_claim,
+        email_claim,
+        algorithms,
+        session_secret,
+        session_lifetime: Duration::hours(session_lifetime_hours),
+    })
+}
+
+/// Parse a network in CIDR notation, or a single IP address. IPv4-mapped IPv6 networks are
+/// converted to IPv4, as that is how peer addresses are compared against them.
+fn parse_network(raw: &str) -> Result<IpNet, ipnet::AddrParseError> {
+    let network = raw
+        .parse()
+        .or_else(|e| raw.parse::<IpAddr>().map(IpNet::from).map_err(|_| e))?;
+
+    match network {
+        IpNet::V6(v6) if v6.prefix_len() >= 96 => match v6.addr().to_ipv4_mapped() {
+            Some(v4) => Ok(IpNet::V4(
+                Ipv4Net::new(v4, v6.prefix_len() - 96).expect("prefix is within range"),
+            )),
+            None => Ok(network),
+        },
+        _ => Ok(network),
+    }
+}
+
+/// A configuration using the defaults from the environment, for use in tests
+#[cfg(test)]
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            database_url: String::new(),
+            path: PathBuf::new(),
+            trash_retention: Duration::days(30),
+            version_limit: 10,
+            version_retention: Duration::days(30),
+            trusted_proxies: vec![
+                parse_network("127.0.0.0/8").unwrap(),
+                parse_network("::1/128").unwrap(),
+            ],
+            sso_secret: None,
+            login_attempt_limit: 5,
+            lockout_duration: Duration::seconds(60),
+            oidc: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_network;
+    use std::net::IpAddr;
+
+    fn contains(network: &str, address: &str) -> bool {
+        let address = address.parse::<IpAddr>().unwrap();
+        parse_network(network).unwrap().contains(&address)
+    }
+
+    #[test]
+    fn cidr_networks() {
+        assert!(contains("10.0.0.0/8", "10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("fd00::/8", "fd12::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+    }
+
+    #[test]
+    fn single_addresses() {
+        assert!(contains("192.168.1.10", "192.168.1.10"));
+        assert!(!contains("192.168.1.10", "192.168.1.11"));
+        assert!(contains("::1", "::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_networks() {
+        // Peers are canonicalized, so mapped networks must match plain IPv4 addresses
+        assert!(contains("::ffff:10.0.0.0/104", "10.20.30.40"));
+        assert!(!contains("::ffff:10.0.0.0/104", "11.0.0.1"));
+        assert!(contains("::ffff:127.0.0.1", "127.0.0.1"));
+        assert_eq!(
+            parse_network("::ffff:10.0.0.0/104").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+    }
+
+    #[test]
+    fn invalid_networks() {
+        assert!(parse_network("10.0.0.0/33").is_err());
+        assert!(parse_network("not-a-network").is_err());
+        assert!(parse_network("").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use super::types::{Action, Capability};
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use async_graphql::SimpleObject;
+use rand_core::OsRng;
+use sqlx::{Error, PgPool, Result};
+use std::path::Path;
+use time::OffsetDateTime;
+
+/// A named token a user can authenticate WebDAV clients with
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct AccessToken {
+    pub id: i32,
+    #[graphql(skip)]
+    pub owner: String,
+    pub name: String,
+    #[graphql(skip)]
+    pub hash: String,
+    /// The only path (and its children) the token can access
+    pub path: Option<String>,
+    /// The most permissive action the token can perform
+    pub action: Option<Action>,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+impl AccessToken {
+    /// Find an access token by its id
+    pub async fn get(db: &PgPool, id: i32) -> Result<Option<AccessToken>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            AccessToken,
+            "SELECT id, owner, name, hash, path, action as \"action: _\", created_at, last_used_at, expires_at \
+            FROM access_tokens WHERE id = $1",
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(token) => Ok(Some(token)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Permanently remove an access token
+    pub async fn delete(db: &PgPool, id: i32) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM access_tokens WHERE id = $1", id)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check if the provided token matches this access token
+    pub fn is_valid(&self, token: &str) -> bool {
+        verify_token(&self.hash, token)
+    }
+
+    /// Check if the token has expired
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= OffsetDateTime::now_utc(),
+            None => false,
+        }
+    }
+
+    /// Check if the token's scope permits the capability for the path
+    pub fn allows(&self, path: &Path, required: Capability) -> bool {
+        let path_allowed = match &self.path {
+            Some(scope) => path.starts_with(scope),
+            None => true,
+        };
+        let action_allowed = match self.action {
+            Some(action) => action.capabilities().contains(required),
+            None => true,
+        };
+
+        path_allowed && action_allowed
+    }
+
+    /// Record that the token was just used
+    pub async fn mark_used(&self, db: &PgPool) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "UPDATE access_tokens SET last_used_at = now() WHERE id = $1",
+            self.id
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Check a token against its stored hash
+pub(super) fn verify_token(hash: &str, token: &str) -> bool {
+    let hash = PasswordHash::new(hash).unwrap();
+
+    let argon2 = Argon2::default();
+    argon2.verify_password(token.as_bytes(), &hash).is_ok()
+}
+
+/// Hash a token for storage
+pub(super) fn hash_token(token: &str) -> String {
+    let argon2 = Argon2::default();
+    let salt = SaltString::generate(&mut OsRng);
+    argon2
+        .hash_password(token.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
@@ -0,0 +1,200 @@
+use super::{
+    permission::{NewPermission, Permission},
+    user::User,
+};
+use crate::error::Error as DavoxideError;
+use async_graphql::{ComplexObject, Context, FieldResult, SimpleObject};
+use sqlx::{Error, PgExecutor, PgPool, Result};
+
+/// A named collection of users that permissions can be assigned to
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Group {
+    pub name: String,
+    pub description: String,
+}
+
+impl Group {
+    /// Get a list of all the groups
+    pub async fn list(db: impl PgExecutor<'_>) -> Result<Vec<Group>> {
+        sqlx::query_as!(Group, "SELECT name, description FROM groups ORDER BY name")
+            .fetch_all(db)
+            .await
+    }
+
+    /// Find a group by its name
+    pub async fn get(db: impl PgExecutor<'_>, name: &str) -> Result<Option<Group>> {
+        let result = sqlx::query_as!(
+            Group,
+            "SELECT name, description FROM groups WHERE name = $1",
+            name
+        )
+        .fetch_one(db)
+        .await;
+
+        match result {
+            Ok(group) => Ok(Some(group)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a new group. Returns [`None`] if a group with the same name already exists.
+    pub async fn create(
+        db: impl PgExecutor<'_>,
+        name: &str,
+        description: &str,
+    ) -> Result<Option<Group>> {
+        sqlx::query_as!(
+            Group,
+            "INSERT INTO groups (name, description) VALUES ($1, $2) \
+            ON CONFLICT (name) DO NOTHING \
+            RETURNING name, description",
+            name,
+            description
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Permanently delete a group, along with its memberships and permissions
+    pub async fn delete(db: &PgPool, name: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM groups WHERE name = $1", name)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Change the group's description
+    pub async fn set_description(
+        &mut self,
+        db: impl PgExecutor<'_>,
+        description: String,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE groups SET description = $1 WHERE name = $2",
+            description,
+            self.name
+        )
+        .execute(db)
+        .await?;
+
+        self.description = description;
+        Ok(())
+    }
+
+    /// Get all the users in the group
+    pub async fn members(&self, db: impl PgExecutor<'_>) -> Result<Vec<User>> {
+        sqlx::query_as!(
+            User,
+            "SELECT username, name, default_access as \"default_access: _\", quota, email FROM users \
+            WHERE username IN (SELECT username FROM group_members WHERE group_name = $1) \
+            ORDER BY username",
+            self.name
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Add a user to the group
+    pub async fn add_member(&self, db: impl PgExecutor<'_>, username: &str) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO group_members (group_name, username) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            self.name,
+            username
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a user from the group
+    pub async fn remove_member(&self, db: impl PgExecutor<'_>, username: &str) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM group_members WHERE group_name = $1 AND username = $2",
+            self.name,
+            username
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get all the permissions for the group
+    pub async fn permissions(&self, db: impl PgExecutor<'_>) -> Result<Vec<Permission>> {
+        sqlx::query_as!(
+            Permission,
+            "SELECT id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until \
+            FROM permissions WHERE applies_to_group = $1",
+            self.name
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Assign a permission to a group, replacing any existing permission for the same path
+    pub async fn assign_permission(
+        &self,
+        db: impl PgExecutor<'_>,
+        permission: &NewPermission,
+    ) -> Result<Permission> {
+        let permission = sqlx::query_as!(
+            Permission,
+            "INSERT INTO permissions \
+            (applies_to_group, path, pattern, action, custom_capabilities, affects_children, valid_from, valid_until) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+            ON CONFLICT ON CONSTRAINT permissions_group_unique DO UPDATE \
+            SET action = excluded.action, custom_capabilities = excluded.custom_capabilities, \
+            affects_children = excluded.affects_children, \
+            valid_from = excluded.valid_from, valid_until = excluded.valid_until \
+            RETURNING id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until",
+            self.name,
+            permission.path,
+            permission.pattern,
+            permission.action as _,
+            permission.custom_capabilities as _,
+            permission.affects_children,
+            permission.valid_from,
+            permission.valid_until
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(permission)
+    }
+}
+
+#[ComplexObject]
+impl Group {
+    #[graphql(name = "members")]
+    async fn members_resolver(&self, ctx: &Context<'_>) -> FieldResult<Vec<User>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let members = self.members(db).await?;
+        Ok(members)
+    }
+
+    #[graphql(name = "permissions")]
+    async fn permissions_resolver(&self, ctx: &Context<'_>) -> FieldResult<Vec<Permission>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let permissions = self.permissions(db).await?;
+        Ok(permissions)
+    }
+}
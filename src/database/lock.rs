@@ -0,0 +1,179 @@
+use async_graphql::SimpleObject;
+use sqlx::{Error, PgPool, Result};
+use time::OffsetDateTime;
+
+/// A WebDAV lock held on a file or folder
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct Lock {
+    pub token: String,
+    /// The locked path, relative to the served directory
+    pub path: String,
+    /// The locked path as it was requested, including the URL prefix
+    #[graphql(skip)]
+    pub href: String,
+    /// The user who created the lock
+    pub principal: Option<String>,
+    /// The owner information provided by the client, as XML
+    pub owner: Option<String>,
+    pub shared: bool,
+    pub deep: bool,
+    #[graphql(skip)]
+    pub timeout_secs: Option<i64>,
+    pub timeout_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+}
+
+impl Lock {
+    /// Get a list of all the active locks
+    pub async fn list(db: &PgPool) -> Result<Vec<Lock>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Lock,
+            "SELECT token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at \
+            FROM locks WHERE timeout_at IS NULL OR timeout_at > now() \
+            ORDER BY path, created_at"
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find an active lock by its token
+    pub async fn get(db: &PgPool, token: &str) -> Result<Option<Lock>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            Lock,
+            "SELECT token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at \
+            FROM locks WHERE token = $1 AND (timeout_at IS NULL OR timeout_at > now())",
+            token
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(lock) => Ok(Some(lock)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the active locks on the path, any of its parents, and any of its children. Locks closer
+    /// to the root are returned first.
+    pub async fn related(db: &PgPool, path: &str) -> Result<Vec<Lock>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Lock,
+            "SELECT token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at \
+            FROM locks \
+            WHERE (timeout_at IS NULL OR timeout_at > now()) \
+            AND (path = '' OR $1 = '' OR path = $1 \
+                OR starts_with($1, path || '/') OR starts_with(path, $1 || '/')) \
+            ORDER BY length(path), created_at",
+            path
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Store a new lock, unless it conflicts with one of the active locks related to its path. The
+    /// locks table is held exclusively while checking, so concurrent requests, even from other
+    /// servers, cannot acquire conflicting locks. Returns the conflicting lock on failure.
+    pub async fn acquire<F>(
+        db: &PgPool,
+        lock: Lock,
+        find_conflict: F,
+    ) -> Result<std::result::Result<Lock, Lock>>
+    where
+        F: FnOnce(&[Lock]) -> Option<Lock>,
+    {
+        let mut tx = db.begin().await?;
+        sqlx::query!("LOCK TABLE locks IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut tx)
+            .await?;
+
+        // Expired locks can be cleaned up while nobody else can modify the table
+        sqlx::query!("DELETE FROM locks WHERE timeout_at <= now()")
+            .execute(&mut tx)
+            .await?;
+
+        let related = sqlx::query_as!(
+            Lock,
+            "SELECT token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at \
+            FROM locks \
+            WHERE path = '' OR $1 = '' OR path = $1 \
+                OR starts_with($1, path || '/') OR starts_with(path, $1 || '/') \
+            ORDER BY length(path), created_at",
+            lock.path
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        if let Some(conflict) = find_conflict(&related) {
+            return Ok(Err(conflict));
+        }
+
+        let lock = sqlx::query_as!(
+            Lock,
+            "INSERT INTO locks (token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+            RETURNING token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at",
+            lock.token,
+            lock.path,
+            lock.href,
+            lock.principal,
+            lock.owner,
+            lock.shared,
+            lock.deep,
+            lock.timeout_secs,
+            lock.timeout_at
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok(lock))
+    }
+
+    /// Extend an active lock by the given timeout. Returns [`None`] if the lock does not exist.
+    pub async fn refresh(
+        db: &PgPool,
+        token: &str,
+        timeout_secs: Option<i64>,
+        timeout_at: Option<OffsetDateTime>,
+    ) -> Result<Option<Lock>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Lock,
+            "UPDATE locks SET timeout_secs = $2, timeout_at = $3 \
+            WHERE token = $1 AND (timeout_at IS NULL OR timeout_at > now()) \
+            RETURNING token, path, href, principal, owner, shared, deep, timeout_secs, timeout_at, created_at",
+            token,
+            timeout_secs,
+            timeout_at
+        )
+        .fetch_optional(&mut conn)
+        .await
+    }
+
+    /// Release a lock
+    pub async fn delete(db: &PgPool, token: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM locks WHERE token = $1", token)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Release all the locks on a path and its children
+    pub async fn delete_within(db: &PgPool, path: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM locks WHERE $1 = '' OR path = $1 OR starts_with(path, $1 || '/')",
+            path
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+}
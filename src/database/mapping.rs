@@ -0,0 +1,277 @@
+use super::{types::Action, user::User};
+use async_graphql::SimpleObject;
+use sqlx::{PgPool, Result};
+
+/// A rule granting access to the members of a group from the SSO proxy or OpenID Connect provider
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct GroupMapping {
+    /// The name of the group as sent by the SSO proxy or OpenID Connect provider
+    pub external_group: String,
+    /// The default access given to members
+    pub default_access: Option<Action>,
+    /// The group members are added to
+    pub group: Option<String>,
+}
+
+impl GroupMapping {
+    /// Get a list of all the group mappings
+    pub async fn list(db: &PgPool) -> Result<Vec<GroupMapping>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            GroupMapping,
+            "SELECT external_group, default_access as \"default_access: _\", group_name as group \
+            FROM group_mappings ORDER BY external_group"
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Create or replace the mapping for an external group
+    pub async fn set(
+        db: &PgPool,
+        external_group: &str,
+        default_access: Option<Action>,
+        group: Option<&str>,
+    ) -> Result<GroupMapping> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            GroupMapping,
+            "INSERT INTO group_mappings (external_group, default_access, group_name) VALUES ($1, $2, $3) \
+            ON CONFLICT (external_group) DO UPDATE \
+            SET default_access = excluded.default_access, group_name = excluded.group_name \
+            RETURNING external_group, default_access as \"default_access: _\", group_name as group",
+            external_group,
+            default_access as _,
+            group
+        )
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    /// Remove the mapping for an external group
+    pub async fn delete(db: &PgPool, external_group: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM group_mappings WHERE external_group = $1",
+            external_group
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Grant the user the access mapped from their external groups. Anything previously granted
+    /// through mappings that no longer match is revoked, while access granted by an admin is left
+    /// untouched. Once an admin sets the user's default access, mappings no longer change it.
+    pub async fn apply(db: &PgPool, user: &mut User, external_groups: &[String]) -> Result<()> {
+        let mut tx = db.begin().await?;
+
+        let matched = sqlx::query_as!(
+            GroupMapping,
+            "SELECT external_group, default_access as \"default_access: _\", group_name as group \
+            FROM group_mappings WHERE external_group = ANY($1)",
+            external_groups
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        // The most permissive matching access wins, otherwise any mapped access falls back to the
+        // default for new users. Access assigned by an admin is never replaced.
+        let access = matched.iter().filter_map(|m| m.default_access).max();
+        let default_access = match access {
+            Some(access) => {
+                sqlx::query_scalar!(
+                    "UPDATE users SET default_access = $2 \
+                    WHERE username = $1 AND managed_access \
+                    RETURNING default_access as \"default_access: Action\"",
+                    user.username,
+                    access as _
+                )
+                .fetch_optional(&mut tx)
+                .await?
+            }
+            None => {
+                sqlx::query_scalar!(
+                    "UPDATE users SET default_access = DEFAULT \
+                    WHERE username = $1 AND managed_access \
+                    RETURNING default_access as \"default_access: Action\"",
+                    user.username
+                )
+                .fetch_optional(&mut tx)
+                .await?
+            }
+        };
+
+        let groups = matched
+            .into_iter()
+            .filter_map(|m| m.group)
+            .collect::<Vec<_>>();
+        sqlx::query!(
+            "DELETE FROM group_members WHERE username = $1 AND managed AND NOT (group_name = ANY($2))",
+            user.username,
+            &groups
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO group_members (group_name, username, managed) \
+            SELECT group_name, $1, true FROM unnest($2::text[]) AS group_name \
+            ON CONFLICT DO NOTHING",
+            user.username,
+            &groups
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        if let Some(default_access) = default_access {
+            user.default_access = default_access;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GroupMapping;
+    use crate::database::{self, Action, Group, User};
+    use sqlx::PgPool;
+    use std::slice;
+    use uuid::Uuid;
+
+    struct Fixture {
+        db: PgPool,
+        user: User,
+        /// A prefix for the names of everything created by this test
+        prefix: String,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let prefix = format!("mapping-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &prefix, "Member")
+                .await
+                .unwrap();
+
+            Some(Fixture { db, user, prefix })
+        }
+
+        /// Map an external group, creating the group members are added to if there is one
+        async fn map(&self, external: &str, access: Option<Action>, group: Option<&str>) -> String {
+            let external = format!("{}-{external}", self.prefix);
+            let group = group.map(|group| format!("{}-{group}", self.prefix));
+            if let Some(group) = &group {
+                Group::create(&self.db, group, "").await.unwrap();
+            }
+
+            GroupMapping::set(&self.db, &external, access, group.as_deref())
+                .await
+                .unwrap();
+            external
+        }
+
+        async fn apply(&mut self, external_groups: &[String]) {
+            GroupMapping::apply(&self.db, &mut self.user, external_groups)
+                .await
+                .unwrap();
+        }
+
+        /// The stored default access, to check it matches what was applied to the user
+        async fn stored_access(&self) -> Action {
+            let user = User::get(&self.db, &self.user.username).await.unwrap();
+            user.unwrap().default_access
+        }
+
+        async fn groups(&self) -> Vec<String> {
+            let groups = self.user.groups(&self.db).await.unwrap();
+            let prefix = format!("{}-", self.prefix);
+            groups
+                .into_iter()
+                .filter_map(|group| Some(group.name.strip_prefix(&prefix)?.to_string()))
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn matching_mappings_apply() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        let readers = fixture
+            .map("readers", Some(Action::Read), Some("staff"))
+            .await;
+        let admins = fixture.map("admins", Some(Action::Admin), None).await;
+        fixture
+            .map("unused", Some(Action::Deny), Some("unused"))
+            .await;
+        fixture.apply(slice::from_ref(&readers)).await;
+
+        assert_eq!(fixture.user.default_access, Action::Read);
+        assert_eq!(fixture.stored_access().await, Action::Read);
+        assert_eq!(fixture.groups().await, vec!["staff"]);
+
+        // The most permissive access wins when several mappings match
+        fixture.apply(&[readers, admins]).await;
+        assert_eq!(fixture.user.default_access, Action::Admin);
+        assert_eq!(fixture.stored_access().await, Action::Admin);
+        assert_eq!(fixture.groups().await, vec!["staff"]);
+    }
+
+    #[tokio::test]
+    async fn no_match_resets_to_default() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        let admins = fixture
+            .map("admins", Some(Action::Admin), Some("admins"))
+            .await;
+        fixture.apply(&[admins]).await;
+        assert_eq!(fixture.user.default_access, Action::Admin);
+
+        // Groups the user was added to by an admin are kept
+        let manual = format!("{}-manual", fixture.prefix);
+        let group = Group::create(&fixture.db, &manual, "").await.unwrap();
+        let group = group.unwrap();
+        group
+            .add_member(&fixture.db, &fixture.user.username)
+            .await
+            .unwrap();
+
+        fixture
+            .apply(&[format!("{}-unknown", fixture.prefix)])
+            .await;
+        assert_eq!(fixture.user.default_access, Action::Modify);
+        assert_eq!(fixture.stored_access().await, Action::Modify);
+        assert_eq!(fixture.groups().await, vec!["manual"]);
+    }
+
+    #[tokio::test]
+    async fn unmanaged_access_is_kept() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        let admins = fixture
+            .map("admins", Some(Action::Admin), Some("admins"))
+            .await;
+
+        let db = fixture.db.clone();
+        fixture
+            .user
+            .set_default_action(&db, Action::Read)
+            .await
+            .unwrap();
+
+        fixture.apply(&[admins]).await;
+        assert_eq!(fixture.user.default_access, Action::Read);
+        assert_eq!(fixture.stored_access().await, Action::Read);
+
+        // Groups are still managed through the mappings
+        assert_eq!(fixture.groups().await, vec!["admins"]);
+        fixture.apply(&[]).await;
+        assert_eq!(fixture.stored_access().await, Action::Read);
+        assert!(fixture.groups().await.is_empty());
+    }
+}
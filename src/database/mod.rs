@@ -6,13 +6,33 @@ use sqlx::{
 use std::str::FromStr;
 use tracing::{info, instrument, log::LevelFilter};
 
+mod access_token;
+mod group;
+mod lock;
+mod mapping;
 mod permission;
+mod property;
+mod quota;
+mod share;
+mod trash;
 mod types;
+mod usage;
 mod user;
+mod version;
 
-pub use permission::Permission;
-pub use types::Action;
+pub use access_token::AccessToken;
+pub use group::Group;
+pub use lock::Lock;
+pub use mapping::GroupMapping;
+pub use permission::{NewPermission, Permission};
+pub use property::Property;
+pub use quota::FolderQuota;
+pub use share::Share;
+pub use trash::TrashedItem;
+pub use types::{Action, Capabilities, Capability};
+pub use usage::FileUsage;
 pub use user::User;
+pub use version::FileVersion;
 
 /// Connect to the database and run any pending migrations
 #[instrument(skip_all)]
@@ -35,3 +55,25 @@ pub async fn connect(url: &str) -> eyre::Result<PgPool> {
 
     Ok(db)
 }
+
+/// Connect to a separate database for tests, creating and migrating it if needed. Returns `None`
+/// when no database is configured so tests that need one can be skipped.
+#[cfg(test)]
+pub async fn connect_for_tests() -> Option<PgPool> {
+    dotenv::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").ok()?;
+    let options = PgConnectOptions::from_str(&url).unwrap();
+
+    // Tests run concurrently, so another may have created the database already
+    let db = PgPool::connect_with(options.clone()).await.unwrap();
+    let _ = sqlx::query("CREATE DATABASE davoxide_test")
+        .execute(&db)
+        .await;
+
+    let db = PgPool::connect_with(options.database("davoxide_test"))
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&db).await.unwrap();
+
+    Some(db)
+}
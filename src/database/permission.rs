@@ -1,25 +1,149 @@
-use super::types::Action;
-use async_graphql::SimpleObject;
-use sqlx::{PgPool, Result};
+use super::types::{Action, Capabilities, Capability};
+use async_graphql::{ComplexObject, SimpleObject};
+use sqlx::{Error, PgExecutor, PgPool, Result};
+use time::OffsetDateTime;
 
 #[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+#[graphql(complex)]
 pub struct Permission {
     pub id: i32,
+    /// The user the permission applies to
     #[graphql(skip)]
-    pub applies_to: String,
+    pub applies_to: Option<String>,
+    /// The group the permission applies to
+    #[graphql(skip)]
+    pub applies_to_group: Option<String>,
     pub path: String,
+    /// Whether the path is a glob pattern rather than an exact path
+    pub pattern: bool,
     pub action: Action,
+    /// The capabilities granted instead of those from the action, if customized
+    #[graphql(skip)]
+    pub custom_capabilities: Option<Capabilities>,
     pub affects_children: bool,
+    /// When the permission starts applying, if not immediately
+    pub valid_from: Option<OffsetDateTime>,
+    /// When the permission stops applying, if ever
+    pub valid_until: Option<OffsetDateTime>,
+}
+
+/// The details of a permission to assign to a user or group
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NewPermission {
+    pub path: String,
+    pub pattern: bool,
+    pub action: Action,
+    pub custom_capabilities: Option<Capabilities>,
+    pub affects_children: bool,
+    pub valid_from: Option<OffsetDateTime>,
+    pub valid_until: Option<OffsetDateTime>,
 }
 
 impl Permission {
-    /// Remove a permission
-    pub async fn delete(db: &PgPool, id: i32) -> Result<()> {
+    /// Get the capabilities the permission grants
+    pub fn capabilities(&self) -> Capabilities {
+        self.custom_capabilities
+            .unwrap_or_else(|| self.action.capabilities())
+    }
+
+    /// Check if the permission applies at the given time
+    pub fn is_active(&self, now: OffsetDateTime) -> bool {
+        self.valid_from.is_none_or(|from| from <= now)
+            && self.valid_until.is_none_or(|until| now < until)
+    }
+
+    /// Find a permission by its ID
+    pub async fn get(db: &PgPool, id: i32) -> Result<Option<Permission>> {
         let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            Permission,
+            "SELECT id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until \
+            FROM permissions WHERE id = $1",
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(permission) => Ok(Some(permission)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Change a permission in place. Any other permission for the same user or group that would
+    /// conflict with the changed one is replaced by it.
+    pub async fn update(db: &PgPool, id: i32, permission: &NewPermission) -> Result<Permission> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "DELETE FROM permissions p USING permissions target \
+            WHERE target.id = $1 AND p.id <> $1 \
+                AND p.applies_to IS NOT DISTINCT FROM target.applies_to \
+                AND p.applies_to_group IS NOT DISTINCT FROM target.applies_to_group \
+                AND p.path = $2 AND p.pattern = $3",
+            id,
+            permission.path,
+            permission.pattern
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let updated = sqlx::query_as!(
+            Permission,
+            "UPDATE permissions SET path = $2, pattern = $3, action = $4, custom_capabilities = $5, \
+            affects_children = $6, valid_from = $7, valid_until = $8 \
+            WHERE id = $1 \
+            RETURNING id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until",
+            id,
+            permission.path,
+            permission.pattern,
+            permission.action as _,
+            permission.custom_capabilities as _,
+            permission.affects_children,
+            permission.valid_from,
+            permission.valid_until
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    /// Remove a permission
+    pub async fn delete(db: impl PgExecutor<'_>, id: i32) -> Result<()> {
         sqlx::query!("DELETE FROM permissions WHERE id = $1", id)
-            .execute(&mut conn)
+            .execute(db)
             .await?;
 
         Ok(())
     }
+
+    /// Remove all the permissions that have stopped applying, returning how many were removed
+    pub async fn delete_expired(db: &PgPool) -> Result<u64> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query!("DELETE FROM permissions WHERE valid_until <= now()")
+            .execute(&mut conn)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[ComplexObject]
+impl Permission {
+    /// The capabilities the permission grants
+    #[graphql(name = "capabilities")]
+    async fn capabilities_resolver(&self) -> Vec<Capability> {
+        self.capabilities().iter().collect()
+    }
+
+    /// Whether the capabilities were customized rather than taken from the action
+    async fn customized(&self) -> bool {
+        self.custom_capabilities.is_some()
+    }
 }
@@ -0,0 +1,147 @@
+use sqlx::{Error, PgPool, Result};
+
+/// A dead property a WebDAV client stored on a file or folder
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Property {
+    /// The path of the file or folder, relative to the served directory
+    pub path: String,
+    /// The XML namespace of the property, empty if it has none
+    pub namespace: String,
+    pub name: String,
+    pub prefix: Option<String>,
+    /// The value of the property as raw XML
+    pub xml: Option<Vec<u8>>,
+}
+
+impl Property {
+    /// Get all the properties of a file or folder
+    pub async fn list(db: &PgPool, path: &str) -> Result<Vec<Property>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Property,
+            "SELECT path, namespace, name, prefix, xml FROM dav_properties \
+            WHERE path = $1 ORDER BY namespace, name",
+            path
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find a single property of a file or folder
+    pub async fn get(
+        db: &PgPool,
+        path: &str,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<Property>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            Property,
+            "SELECT path, namespace, name, prefix, xml FROM dav_properties \
+            WHERE path = $1 AND namespace = $2 AND name = $3",
+            path,
+            namespace,
+            name
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(property) => Ok(Some(property)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create or replace the property
+    pub async fn save(&self, db: &PgPool) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "INSERT INTO dav_properties (path, namespace, name, prefix, xml) \
+            VALUES ($1, $2, $3, $4, $5) \
+            ON CONFLICT (path, namespace, name) DO UPDATE SET prefix = excluded.prefix, xml = excluded.xml",
+            self.path,
+            self.namespace,
+            self.name,
+            self.prefix,
+            self.xml
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a single property from a file or folder
+    pub async fn delete(db: &PgPool, path: &str, namespace: &str, name: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM dav_properties WHERE path = $1 AND namespace = $2 AND name = $3",
+            path,
+            namespace,
+            name
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove all the properties of a path and its children
+    pub async fn delete_within(db: &PgPool, path: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM dav_properties WHERE path = $1 OR starts_with(path, $1 || '/')",
+            path
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Copy the properties of a path and its children to a new location, replacing any properties
+    /// already at the destination
+    pub async fn copy_within(db: &PgPool, from: &str, to: &str) -> Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "DELETE FROM dav_properties WHERE path = $1 OR starts_with(path, $1 || '/')",
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO dav_properties (path, namespace, name, prefix, xml) \
+            SELECT $2 || substr(path, length($1) + 1), namespace, name, prefix, xml \
+            FROM dav_properties WHERE path = $1 OR starts_with(path, $1 || '/')",
+            from,
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    /// Move the properties of a path and its children to a new location, replacing any properties
+    /// already at the destination
+    pub async fn move_within(db: &PgPool, from: &str, to: &str) -> Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "DELETE FROM dav_properties WHERE path = $1 OR starts_with(path, $1 || '/')",
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE dav_properties SET path = $2 || substr(path, length($1) + 1) \
+            WHERE path = $1 OR starts_with(path, $1 || '/')",
+            from,
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await
+    }
+}
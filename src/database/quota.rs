@@ -0,0 +1,103 @@
+use async_graphql::SimpleObject;
+use sqlx::{Error, PgPool, Result};
+
+/// A limit on the number of bytes that can be stored within a top-level folder
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct FolderQuota {
+    pub folder: String,
+    pub quota: i64,
+    /// The number of bytes stored within the folder
+    pub used: i64,
+}
+
+impl FolderQuota {
+    /// Get a list of all the folder quotas
+    pub async fn list(db: &PgPool) -> Result<Vec<FolderQuota>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            FolderQuota,
+            "SELECT folder, quota, used FROM folder_quotas ORDER BY folder"
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find the quota for a top-level folder
+    pub async fn get(db: &PgPool, folder: &str) -> Result<Option<FolderQuota>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            FolderQuota,
+            "SELECT folder, quota, used FROM folder_quotas WHERE folder = $1",
+            folder
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(quota) => Ok(Some(quota)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create or replace the quota for a top-level folder, along with the number of bytes
+    /// currently stored within it
+    pub async fn set(db: &PgPool, folder: &str, quota: i64, used: i64) -> Result<FolderQuota> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            FolderQuota,
+            "INSERT INTO folder_quotas (folder, quota, used) VALUES ($1, $2, $3) \
+            ON CONFLICT (folder) DO UPDATE SET quota = excluded.quota, used = excluded.used \
+            RETURNING folder, quota, used",
+            folder,
+            quota,
+            used
+        )
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    /// Replace the number of bytes recorded as stored within a top-level folder
+    pub async fn set_usage(db: &PgPool, folder: &str, used: i64) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "UPDATE folder_quotas SET used = $2 WHERE folder = $1",
+            folder,
+            used
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Add to, or subtract from, the usage of the top-level folder containing the path. Nothing
+    /// is recorded if the folder does not have a quota.
+    pub async fn adjust_usage(db: &PgPool, path: &str, delta: i64) -> Result<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "UPDATE folder_quotas SET used = greatest(used + $2, 0) \
+            WHERE folder = split_part($1, '/', 1)",
+            path,
+            delta
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove the quota from a top-level folder
+    pub async fn delete(db: &PgPool, folder: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM folder_quotas WHERE folder = $1", folder)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use super::access_token::verify_token;
+use async_graphql::{ComplexObject, SimpleObject};
+use rand_core::{OsRng, RngCore};
+use sqlx::{Error, PgPool, Result};
+use time::OffsetDateTime;
+
+const SLUG_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const SLUG_LENGTH: usize = 12;
+
+/// A public link to a file or folder that can be accessed without authenticating
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Share {
+    pub id: i32,
+    pub slug: String,
+    #[graphql(skip)]
+    pub owner: String,
+    pub path: String,
+    #[graphql(skip)]
+    pub password: Option<String>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_downloads: Option<i32>,
+    pub downloads: i32,
+    pub created_at: OffsetDateTime,
+}
+
+impl Share {
+    /// Find a share by its id
+    pub async fn get(db: &PgPool, id: i32) -> Result<Option<Share>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            Share,
+            "SELECT id, slug, owner, path, password, expires_at, max_downloads, downloads, created_at \
+            FROM shares WHERE id = $1",
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(share) => Ok(Some(share)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Find a share by its public slug
+    pub async fn get_by_slug(db: &PgPool, slug: &str) -> Result<Option<Share>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            Share,
+            "SELECT id, slug, owner, path, password, expires_at, max_downloads, downloads, created_at \
+            FROM shares WHERE slug = $1",
+            slug
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(share) => Ok(Some(share)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Permanently remove a share
+    pub async fn delete(db: &PgPool, id: i32) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM shares WHERE id = $1", id)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check if the share has neither expired nor reached its download limit
+    pub fn is_available(&self) -> bool {
+        let expired = matches!(self.expires_at, Some(e) if e <= OffsetDateTime::now_utc());
+        let exhausted = matches!(self.max_downloads, Some(max) if self.downloads >= max);
+
+        !expired && !exhausted
+    }
+
+    /// Check if the provided password unlocks the share. Shares without a password are always
+    /// unlocked.
+    pub fn password_valid(&self, password: Option<&str>) -> bool {
+        match (&self.password, password) {
+            (Some(hash), Some(password)) => verify_token(hash, password),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Count a download against the share's limit. Returns `false` if the limit has already been
+    /// reached.
+    pub async fn record_download(&self, db: &PgPool) -> Result<bool> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query!(
+            "UPDATE shares SET downloads = downloads + 1 \
+            WHERE id = $1 AND (max_downloads IS NULL OR downloads < max_downloads)",
+            self.id
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+#[ComplexObject]
+impl Share {
+    async fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+}
+
+/// Generate a random slug for a share
+pub(super) fn generate_slug() -> String {
+    let mut bytes = [0u8; SLUG_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes
+        .iter()
+        .map(|b| SLUG_ALPHABET[*b as usize % SLUG_ALPHABET.len()] as char)
+        .collect()
+}
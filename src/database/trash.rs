@@ -0,0 +1,109 @@
+use async_graphql::SimpleObject;
+use sqlx::{Error, PgPool, Result};
+use time::OffsetDateTime;
+
+/// A deleted file or folder that can still be restored
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct TrashedItem {
+    pub id: i32,
+    /// Where the item was before it was deleted
+    pub original_path: String,
+    /// The name of the item within the trash directory
+    #[graphql(skip)]
+    pub name: String,
+    pub is_directory: bool,
+    pub deleted_by: Option<String>,
+    pub deleted_at: OffsetDateTime,
+}
+
+impl TrashedItem {
+    /// Get a list of everything in the trash
+    pub async fn list(db: &PgPool) -> Result<Vec<TrashedItem>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            TrashedItem,
+            "SELECT id, original_path, name, is_directory, deleted_by, deleted_at FROM trash \
+            ORDER BY deleted_at DESC"
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Get a list of everything the user has deleted
+    pub async fn list_for(db: &PgPool, username: &str) -> Result<Vec<TrashedItem>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            TrashedItem,
+            "SELECT id, original_path, name, is_directory, deleted_by, deleted_at FROM trash \
+            WHERE deleted_by = $1 ORDER BY deleted_at DESC",
+            username
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Get a list of everything deleted before the cutoff
+    pub async fn deleted_before(db: &PgPool, cutoff: OffsetDateTime) -> Result<Vec<TrashedItem>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            TrashedItem,
+            "SELECT id, original_path, name, is_directory, deleted_by, deleted_at FROM trash \
+            WHERE deleted_at < $1",
+            cutoff
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find an item in the trash by its id
+    pub async fn get(db: &PgPool, id: i32) -> Result<Option<TrashedItem>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            TrashedItem,
+            "SELECT id, original_path, name, is_directory, deleted_by, deleted_at FROM trash \
+            WHERE id = $1",
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(item) => Ok(Some(item)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record an item that was moved to the trash
+    pub async fn create(
+        db: &PgPool,
+        original_path: &str,
+        name: &str,
+        is_directory: bool,
+        deleted_by: &str,
+    ) -> Result<TrashedItem> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            TrashedItem,
+            "INSERT INTO trash (original_path, name, is_directory, deleted_by) \
+            VALUES ($1, $2, $3, $4) \
+            RETURNING id, original_path, name, is_directory, deleted_by, deleted_at",
+            original_path,
+            name,
+            is_directory,
+            deleted_by
+        )
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    /// Forget about an item in the trash
+    pub async fn delete(db: &PgPool, id: i32) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM trash WHERE id = $1", id)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
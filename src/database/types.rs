@@ -1,8 +1,28 @@
 use async_graphql::Enum;
-use sqlx::Type;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+    Decode, Encode, Postgres, Type,
+};
 
-/// The actions a user is allowed to perform
-#[derive(Clone, Copy, Debug, Enum, Eq, Ord, PartialEq, PartialOrd, Type)]
+/// The actions a user is allowed to perform. Each action is a preset set of capabilities.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Enum,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+    Type,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "action", rename_all = "lowercase")]
 pub enum Action {
     Deny,
@@ -10,3 +30,121 @@ pub enum Action {
     Modify,
     Admin,
 }
+
+impl Action {
+    /// Get the capabilities the action grants
+    pub fn capabilities(self) -> Capabilities {
+        use Capability::*;
+
+        match self {
+            Action::Deny => Capabilities::NONE,
+            Action::Read => Capabilities::from_iter([List, Read, Share]),
+            Action::Modify => {
+                Capabilities::from_iter([List, Read, Share, Create, Overwrite, Delete])
+            }
+            Action::Admin => Capabilities::ALL,
+        }
+    }
+}
+
+/// An individual operation a permission can allow
+#[derive(Clone, Copy, Debug, Deserialize, Enum, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    /// List the contents of directories and view properties
+    List,
+    /// Download the contents of files
+    Read,
+    /// Upload new files and create directories
+    Create,
+    /// Replace the contents or properties of existing files
+    Overwrite,
+    /// Delete or move files and directories
+    Delete,
+    /// Lock files and directories
+    Lock,
+    /// Share files and directories through public links
+    Share,
+}
+
+impl Capability {
+    const ALL: [Capability; 7] = [
+        Capability::List,
+        Capability::Read,
+        Capability::Create,
+        Capability::Overwrite,
+        Capability::Delete,
+        Capability::Lock,
+        Capability::Share,
+    ];
+
+    /// The bit representing the capability in a set
+    fn bit(self) -> i32 {
+        1 << self as i32
+    }
+}
+
+/// A set of capabilities, stored in the database as a bit mask
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Capabilities(i32);
+
+impl Capabilities {
+    /// A set without any capabilities
+    pub const NONE: Capabilities = Capabilities(0);
+    /// A set with every capability
+    pub const ALL: Capabilities = Capabilities((1 << Capability::ALL.len()) - 1);
+
+    /// Check if the capability is in the set
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Check if the set has no capabilities
+    pub fn is_empty(self) -> bool {
+        self.0 & Capabilities::ALL.0 == 0
+    }
+
+    /// Combine the capabilities in both sets
+    pub fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    /// Get the number of capabilities in the set
+    pub fn len(self) -> usize {
+        (self.0 & Capabilities::ALL.0).count_ones() as usize
+    }
+
+    /// Get the capabilities in the set
+    pub fn iter(self) -> impl Iterator<Item = Capability> {
+        Capability::ALL
+            .into_iter()
+            .filter(move |&capability| self.contains(capability))
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Capabilities::NONE, |set, capability| {
+                Capabilities(set.0 | capability.bit())
+            })
+    }
+}
+
+impl Type<Postgres> for Capabilities {
+    fn type_info() -> PgTypeInfo {
+        <i32 as Type<Postgres>>::type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for Capabilities {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <i32 as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl Decode<'_, Postgres> for Capabilities {
+    fn decode(value: PgValueRef<'_>) -> Result<Self, BoxDynError> {
+        <i32 as Decode<Postgres>>::decode(value).map(Capabilities)
+    }
+}
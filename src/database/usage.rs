@@ -0,0 +1,97 @@
+use crate::trash::TRASH_DIR;
+use sqlx::{Error, PgPool, Result};
+
+/// The size of a file uploaded by a user, counted against their quota
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileUsage {
+    /// The path of the file, relative to the served directory
+    pub path: String,
+    pub owner: String,
+    pub size: i64,
+}
+
+impl FileUsage {
+    /// Find the usage recorded for a file
+    pub async fn get(db: &PgPool, path: &str) -> Result<Option<FileUsage>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            FileUsage,
+            "SELECT path, owner, size FROM file_usage WHERE path = $1",
+            path
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(usage) => Ok(Some(usage)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record the owner and size of a file, replacing anything previously recorded for it
+    pub async fn record(db: &PgPool, path: &str, owner: &str, size: i64) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "INSERT INTO file_usage (path, owner, size) VALUES ($1, $2, $3) \
+            ON CONFLICT (path) DO UPDATE SET owner = excluded.owner, size = excluded.size",
+            path,
+            owner,
+            size
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the total number of bytes stored by a user, not counting anything in the trash
+    pub async fn total_for(db: &PgPool, owner: &str) -> Result<i64> {
+        let mut conn = db.acquire().await?;
+        let total = sqlx::query_scalar!(
+            "SELECT coalesce(sum(size), 0)::bigint as \"total!\" FROM file_usage \
+            WHERE owner = $1 AND NOT starts_with(path, $2 || '/')",
+            owner,
+            TRASH_DIR
+        )
+        .fetch_one(&mut conn)
+        .await?;
+
+        Ok(total)
+    }
+
+    /// Forget the usage of a path and its children
+    pub async fn delete_within(db: &PgPool, path: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "DELETE FROM file_usage WHERE path = $1 OR starts_with(path, $1 || '/')",
+            path
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Move the usage of a path and its children to a new location, replacing anything already
+    /// recorded at the destination
+    pub async fn move_within(db: &PgPool, from: &str, to: &str) -> Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "DELETE FROM file_usage WHERE path = $1 OR starts_with(path, $1 || '/')",
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE file_usage SET path = $2 || substr(path, length($1) + 1) \
+            WHERE path = $1 OR starts_with(path, $1 || '/')",
+            from,
+            to
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await
+    }
+}
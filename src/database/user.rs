@@ -1,56 +1,81 @@
-use super::{permission::Permission, types::Action};
+use super::{
+    access_token::{hash_token, AccessToken},
+    group::Group,
+    permission::{NewPermission, Permission},
+    share::{generate_slug, Share},
+    types::Action,
+    usage::FileUsage,
+};
 use crate::error::Error as DavoxideError;
-use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use async_graphql::{ComplexObject, Context, FieldResult, SimpleObject};
-use rand_core::OsRng;
-use sqlx::{Error, PgPool, Result};
+use sqlx::{Error, PgExecutor, PgPool, Result};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
+/// What was removed or reassigned when a user was deleted
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UserDeletion {
+    pub permissions: u64,
+    pub access_tokens: u64,
+    pub shares: u64,
+    pub locks: u64,
+    pub group_memberships: u64,
+    pub reassigned_shares: u64,
+    /// The number of stored files whose usage now counts against the new owner's quota
+    pub reassigned_usage: u64,
+}
+
 /// An individual user with access to the application
 #[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
 #[graphql(complex)]
 pub struct User {
     pub username: String,
     pub name: String,
-    #[graphql(skip)]
-    pub access_token: Option<String>,
     pub default_access: Action,
+    /// The most bytes the user can store, if limited
+    pub quota: Option<i64>,
+    /// The email address sent by the SSO proxy or OpenID Connect provider
+    pub email: Option<String>,
 }
 
 impl User {
     /// Get a list of all the users
-    pub async fn list(db: &PgPool) -> Result<Vec<User>> {
-        let mut conn = db.acquire().await?;
-        sqlx::query_as!(User, "SELECT username, name, access_token, default_access as \"default_access: _\" FROM users")
-            .fetch_all(&mut conn)
-            .await
+    pub async fn list(db: impl PgExecutor<'_>) -> Result<Vec<User>> {
+        sqlx::query_as!(
+            User,
+            "SELECT username, name, default_access as \"default_access: _\", quota, email FROM users"
+        )
+        .fetch_all(db)
+        .await
     }
 
     /// Create a user if they do not already exist
-    pub async fn create_if_not_exists(db: &PgPool, username: &str, name: &str) -> Result<User> {
-        let mut conn = db.acquire().await?;
+    pub async fn create_if_not_exists(
+        db: impl PgExecutor<'_>,
+        username: &str,
+        name: &str,
+    ) -> Result<User> {
         sqlx::query_as!(
             User,
             "INSERT INTO users (username, name) VALUES ($1, $2) \
             ON CONFLICT (username) DO UPDATE SET name = excluded.name \
-            RETURNING username, name, access_token, default_access as \"default_access: _\"",
+            RETURNING username, name, default_access as \"default_access: _\", quota, email",
             username,
             name
         )
-        .fetch_one(&mut conn)
+        .fetch_one(db)
         .await
     }
 
     /// Find a user by their username
-    pub async fn get(db: &PgPool, username: &str) -> Result<Option<User>> {
-        let mut conn = db.acquire().await?;
+    pub async fn get(db: impl PgExecutor<'_>, username: &str) -> Result<Option<User>> {
         let result = sqlx::query_as!(
             User,
-            "SELECT username, name, access_token, default_access as \"default_access: _\" FROM users \
+            "SELECT username, name, default_access as \"default_access: _\", quota, email FROM users \
             WHERE username = $1",
             username
         )
-        .fetch_one(&mut conn)
+        .fetch_one(db)
         .await;
 
         match result {
@@ -60,48 +85,169 @@ impl User {
         }
     }
 
-    /// Permanently delete a user
-    pub async fn delete(db: &PgPool, username: &str) -> Result<()> {
-        let mut conn = db.acquire().await?;
-        sqlx::query!("DELETE FROM users WHERE username = $1", username)
-            .execute(&mut conn)
+    /// Permanently delete a user, along with their permissions, access tokens, locks and group
+    /// memberships. Their shares and the usage recorded for their stored files are either
+    /// reassigned to another user or removed, as are the trashed items and file versions they
+    /// created. Otherwise, trashed items and file versions are kept without an owner. Files on
+    /// disk are never removed.
+    pub async fn delete(
+        db: &PgPool,
+        username: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<UserDeletion> {
+        let mut tx = db.begin().await?;
+        let mut deletion = UserDeletion::default();
+
+        if let Some(new_owner) = reassign_to {
+            deletion.reassigned_shares = sqlx::query!(
+                "UPDATE shares SET owner = $2 WHERE owner = $1",
+                username,
+                new_owner
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            deletion.reassigned_usage = sqlx::query!(
+                "UPDATE file_usage SET owner = $2 WHERE owner = $1",
+                username,
+                new_owner
+            )
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+            sqlx::query!(
+                "UPDATE trash SET deleted_by = $2 WHERE deleted_by = $1",
+                username,
+                new_owner
+            )
+            .execute(&mut tx)
             .await?;
+            sqlx::query!(
+                "UPDATE file_versions SET created_by = $2 WHERE created_by = $1",
+                username,
+                new_owner
+            )
+            .execute(&mut tx)
+            .await?;
+        }
 
-        Ok(())
+        // Everything else is removed along with the user, but is counted first so it can be
+        // reported
+        deletion.permissions =
+            sqlx::query!("DELETE FROM permissions WHERE applies_to = $1", username)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        deletion.access_tokens =
+            sqlx::query!("DELETE FROM access_tokens WHERE owner = $1", username)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        deletion.shares = sqlx::query!("DELETE FROM shares WHERE owner = $1", username)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        deletion.locks = sqlx::query!("DELETE FROM locks WHERE principal = $1", username)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        deletion.group_memberships =
+            sqlx::query!("DELETE FROM group_members WHERE username = $1", username)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+
+        let result = sqlx::query!("DELETE FROM users WHERE username = $1", username)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        tx.commit().await?;
+        Ok(deletion)
     }
 
-    /// Check if the provided token is valid. If the user has no access token, the provided token
-    /// will always be reported as invalid
-    pub fn access_token_valid(&self, token: &str) -> bool {
-        if let Some(hash) = &self.access_token {
-            let hash = PasswordHash::new(hash).unwrap();
+    /// Get all the access tokens belonging to the user, including expired ones
+    pub async fn access_tokens(&self, db: &PgPool) -> Result<Vec<AccessToken>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            AccessToken,
+            "SELECT id, owner, name, hash, path, action as \"action: _\", created_at, last_used_at, expires_at \
+            FROM access_tokens WHERE owner = $1 ORDER BY created_at",
+            self.username
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find the user's unexpired access token matching the provided token. The token's last used
+    /// time is updated when a match is found.
+    pub async fn authenticate(&self, db: &PgPool, token: &str) -> Result<Option<AccessToken>> {
+        let mut conn = db.acquire().await?;
+        let candidates = sqlx::query_as!(
+            AccessToken,
+            "SELECT id, owner, name, hash, path, action as \"action: _\", created_at, last_used_at, expires_at \
+            FROM access_tokens WHERE owner = $1 AND (expires_at IS NULL OR expires_at > now())",
+            self.username
+        )
+        .fetch_all(&mut conn)
+        .await?;
 
-            // Check the hash
-            let argon2 = Argon2::default();
-            argon2.verify_password(token.as_bytes(), &hash).is_ok()
-        } else {
-            false
+        match candidates.into_iter().find(|t| t.is_valid(token)) {
+            Some(access_token) => {
+                access_token.mark_used(db).await?;
+                Ok(Some(access_token))
+            }
+            None => Ok(None),
         }
     }
 
-    /// Re-generate a user's access token
-    pub async fn regenerate_access_token(&self, db: &PgPool) -> Result<String> {
+    /// Create a new named access token for the user. Returns [`None`] if the user already has a
+    /// token with the same name.
+    pub async fn create_access_token(
+        &self,
+        db: &PgPool,
+        name: &str,
+        path: Option<&str>,
+        action: Option<Action>,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<Option<(AccessToken, String)>> {
         let token = Uuid::new_v4().to_string();
+        let hash = hash_token(&token);
 
-        // Hash the token
-        let argon2 = Argon2::default();
-        let salt = SaltString::generate(&mut OsRng);
-        let hash = argon2
-            .hash_password(token.as_bytes(), &salt)
-            .unwrap()
-            .to_string();
+        let mut conn = db.acquire().await?;
+        let access_token = sqlx::query_as!(
+            AccessToken,
+            "INSERT INTO access_tokens (owner, name, hash, path, action, expires_at) \
+            VALUES ($1, $2, $3, $4, $5, $6) \
+            ON CONFLICT (owner, name) DO NOTHING \
+            RETURNING id, owner, name, hash, path, action as \"action: _\", created_at, last_used_at, expires_at",
+            self.username,
+            name,
+            hash,
+            path,
+            action as _,
+            expires_at
+        )
+        .fetch_optional(&mut conn)
+        .await?;
+
+        Ok(access_token.map(|access_token| (access_token, token)))
+    }
+
+    /// Re-generate the user's default access token, creating it if it does not exist
+    pub async fn regenerate_access_token(&self, db: &PgPool) -> Result<String> {
+        let token = Uuid::new_v4().to_string();
+        let hash = hash_token(&token);
 
-        // Update the user
         let mut conn = db.acquire().await?;
         sqlx::query!(
-            "UPDATE users SET access_token = $1 WHERE username = $2",
-            hash,
-            self.username
+            "INSERT INTO access_tokens (owner, name, hash) VALUES ($1, 'default', $2) \
+            ON CONFLICT (owner, name) DO UPDATE \
+            SET hash = excluded.hash, path = null, action = null, created_at = now(), last_used_at = null, expires_at = null",
+            self.username,
+            hash
         )
         .execute(&mut conn)
         .await?;
@@ -109,30 +255,103 @@ impl User {
         Ok(token)
     }
 
-    /// Remove a user's access token
-    pub async fn revoke_access_token(&self, db: &PgPool) -> Result<()> {
+    /// Remove all of a user's access tokens
+    pub async fn revoke_access_tokens(&self, db: &PgPool) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM access_tokens WHERE owner = $1", self.username)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get all the shares the user has created
+    pub async fn shares(&self, db: &PgPool) -> Result<Vec<Share>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Share,
+            "SELECT id, slug, owner, path, password, expires_at, max_downloads, downloads, created_at \
+            FROM shares WHERE owner = $1 ORDER BY created_at",
+            self.username
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Create a public share link for a path
+    pub async fn create_share(
+        &self,
+        db: &PgPool,
+        path: &str,
+        password: Option<&str>,
+        expires_at: Option<OffsetDateTime>,
+        max_downloads: Option<i32>,
+    ) -> Result<Share> {
+        let slug = generate_slug();
+        let password = password.map(hash_token);
+
         let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Share,
+            "INSERT INTO shares (slug, owner, path, password, expires_at, max_downloads) \
+            VALUES ($1, $2, $3, $4, $5, $6) \
+            RETURNING id, slug, owner, path, password, expires_at, max_downloads, downloads, created_at",
+            slug,
+            self.username,
+            path,
+            password,
+            expires_at,
+            max_downloads
+        )
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    /// Change the default action for the user. The action is no longer managed through group
+    /// mappings.
+    pub async fn set_default_action(
+        &mut self,
+        db: impl PgExecutor<'_>,
+        action: Action,
+    ) -> Result<()> {
         sqlx::query!(
-            "UPDATE users SET access_token = null WHERE username = $1",
+            "UPDATE users SET default_access = $1, managed_access = false WHERE username = $2",
+            action as _,
             self.username
         )
-        .execute(&mut conn)
+        .execute(db)
         .await?;
 
+        self.default_access = action;
         Ok(())
     }
 
-    /// Change the default action for the user
-    pub async fn set_default_action(&mut self, db: &PgPool, action: Action) -> Result<()> {
+    /// Change the user's email address, removing it if [`None`]
+    pub async fn set_email(&mut self, db: &PgPool, email: Option<&str>) -> Result<()> {
         let mut conn = db.acquire().await?;
         sqlx::query!(
-            "UPDATE users SET default_access = $1 WHERE username = $2",
-            action as _,
+            "UPDATE users SET email = $1 WHERE username = $2",
+            email,
             self.username
         )
         .execute(&mut conn)
         .await?;
 
+        self.email = email.map(String::from);
+        Ok(())
+    }
+
+    /// Change the number of bytes the user can store, removing the limit if [`None`]
+    pub async fn set_quota(&mut self, db: impl PgExecutor<'_>, quota: Option<i64>) -> Result<()> {
+        sqlx::query!(
+            "UPDATE users SET quota = $1 WHERE username = $2",
+            quota,
+            self.username
+        )
+        .execute(db)
+        .await?;
+
+        self.quota = quota;
         Ok(())
     }
 
@@ -142,12 +361,31 @@ impl User {
     }
 
     /// Get all the permissions for the user
-    pub async fn permissions(&self, db: &PgPool) -> Result<Vec<Permission>> {
+    pub async fn permissions(&self, db: impl PgExecutor<'_>) -> Result<Vec<Permission>> {
+        let permissions = sqlx::query_as!(
+            Permission,
+            "SELECT id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until \
+            FROM permissions WHERE applies_to = $1",
+            self.username
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(permissions)
+    }
+
+    /// Get all the permissions that affect the user, including those inherited from their groups
+    pub async fn effective_permissions(&self, db: &PgPool) -> Result<Vec<Permission>> {
         let mut conn = db.acquire().await?;
         let permissions = sqlx::query_as!(
             Permission,
-            "SELECT id, applies_to, path, action as \"action: _\", affects_children FROM permissions \
-            WHERE applies_to = $1",
+            "SELECT id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until \
+            FROM permissions WHERE applies_to = $1 \
+            OR applies_to_group IN (SELECT group_name FROM group_members WHERE username = $1)",
             self.username
         )
         .fetch_all(&mut conn)
@@ -156,26 +394,48 @@ impl User {
         Ok(permissions)
     }
 
-    /// Assign a permission to a user
+    /// Get all the groups the user is a member of
+    pub async fn groups(&self, db: &PgPool) -> Result<Vec<Group>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            Group,
+            "SELECT name, description FROM groups \
+            WHERE name IN (SELECT group_name FROM group_members WHERE username = $1) \
+            ORDER BY name",
+            self.username
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Assign a permission to a user, replacing any existing permission for the same path
     pub async fn assign_permission(
         &self,
-        db: &PgPool,
-        path: &str,
-        action: Action,
-        affects_children: bool,
+        db: impl PgExecutor<'_>,
+        permission: &NewPermission,
     ) -> Result<Permission> {
-        let mut conn = db.acquire().await?;
         let permission = sqlx::query_as!(
             Permission,
-            "INSERT INTO permissions (applies_to, path, action, affects_children) \
-            VALUES ($1, $2, $3, $4) \
-            RETURNING id, applies_to, path, action as \"action: _\", affects_children",
+            "INSERT INTO permissions \
+            (applies_to, path, pattern, action, custom_capabilities, affects_children, valid_from, valid_until) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+            ON CONFLICT ON CONSTRAINT permissions_user_unique DO UPDATE \
+            SET action = excluded.action, custom_capabilities = excluded.custom_capabilities, \
+            affects_children = excluded.affects_children, \
+            valid_from = excluded.valid_from, valid_until = excluded.valid_until \
+            RETURNING id, applies_to, applies_to_group, path, pattern, action as \"action: _\", \
+            custom_capabilities as \"custom_capabilities: _\", affects_children, \
+            valid_from, valid_until",
             self.username,
-            path,
-            action as _,
-            affects_children
+            permission.path,
+            permission.pattern,
+            permission.action as _,
+            permission.custom_capabilities as _,
+            permission.affects_children,
+            permission.valid_from,
+            permission.valid_until
         )
-        .fetch_one(&mut conn)
+        .fetch_one(db)
         .await?;
 
         Ok(permission)
@@ -190,7 +450,32 @@ impl User {
             return Err(DavoxideError::InvalidPermissions.into());
         }
 
-        Ok(self.access_token.is_some())
+        let db = ctx.data::<PgPool>()?;
+        let access_tokens = self.access_tokens(db).await?;
+        Ok(access_tokens.iter().any(|t| !t.is_expired()))
+    }
+
+    async fn storage_used(&self, ctx: &Context<'_>) -> FieldResult<i64> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() && current_user.username != self.username {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let used = FileUsage::total_for(db, &self.username).await?;
+        Ok(used)
+    }
+
+    #[graphql(name = "accessTokens")]
+    async fn access_tokens_resolver(&self, ctx: &Context<'_>) -> FieldResult<Vec<AccessToken>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() && current_user.username != self.username {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let access_tokens = self.access_tokens(db).await?;
+        Ok(access_tokens)
     }
 
     #[graphql(name = "permissions")]
@@ -204,4 +489,285 @@ impl User {
         let permissions = self.permissions(db).await?;
         Ok(permissions)
     }
+
+    #[graphql(name = "shares")]
+    async fn shares_resolver(&self, ctx: &Context<'_>) -> FieldResult<Vec<Share>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() && current_user.username != self.username {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let shares = self.shares(db).await?;
+        Ok(shares)
+    }
+
+    #[graphql(name = "groups")]
+    async fn groups_resolver(&self, ctx: &Context<'_>) -> FieldResult<Vec<Group>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() && current_user.username != self.username {
+            return Err(DavoxideError::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let groups = self.groups(db).await?;
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{User, UserDeletion};
+    use crate::database::{
+        self, AccessToken, Action, FileUsage, FileVersion, Group, Lock, NewPermission, Permission,
+        Share, TrashedItem,
+    };
+    use sqlx::{Error, PgPool};
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    /// Everything a user can own or create
+    struct Owned {
+        permission: Permission,
+        access_token: AccessToken,
+        share: Share,
+        lock: Lock,
+        usage: Vec<String>,
+        trashed: TrashedItem,
+        version: FileVersion,
+    }
+
+    struct Fixture {
+        db: PgPool,
+        user: User,
+        other: User,
+        owned: Owned,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let prefix = format!("delete-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &prefix, "Leaving")
+                .await
+                .unwrap();
+            let other = User::create_if_not_exists(&db, &format!("{prefix}-other"), "Staying")
+                .await
+                .unwrap();
+
+            let owned = Fixture::populate(&db, &user, &prefix).await;
+            Some(Fixture {
+                db,
+                user,
+                other,
+                owned,
+            })
+        }
+
+        async fn populate(db: &PgPool, user: &User, prefix: &str) -> Owned {
+            let permission = NewPermission {
+                path: format!("{prefix}/private"),
+                pattern: false,
+                action: Action::Deny,
+                custom_capabilities: None,
+                affects_children: true,
+                valid_from: None,
+                valid_until: None,
+            };
+            let permission = user.assign_permission(db, &permission).await.unwrap();
+
+            let (access_token, _) = user
+                .create_access_token(db, "laptop", None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let share = user
+                .create_share(db, &format!("{prefix}/shared"), None, None, None)
+                .await
+                .unwrap();
+
+            let lock = Lock {
+                token: Uuid::new_v4().urn().to_string(),
+                path: format!("{prefix}/locked"),
+                href: format!("/dav/{prefix}/locked"),
+                principal: Some(user.username.clone()),
+                owner: None,
+                shared: false,
+                deep: false,
+                timeout_secs: None,
+                timeout_at: None,
+                created_at: OffsetDateTime::now_utc(),
+            };
+            let lock = Lock::acquire(db, lock, |_| None).await.unwrap().unwrap();
+
+            let group = Group::create(db, prefix, "").await.unwrap().unwrap();
+            group.add_member(db, &user.username).await.unwrap();
+
+            let usage = vec![format!("{prefix}/a"), format!("{prefix}/b")];
+            for path in &usage {
+                FileUsage::record(db, path, &user.username, 10)
+                    .await
+                    .unwrap();
+            }
+
+            let trashed =
+                TrashedItem::create(db, &format!("{prefix}/old"), prefix, false, &user.username)
+                    .await
+                    .unwrap();
+            let version = FileVersion::create(
+                db,
+                &format!("{prefix}/a"),
+                prefix,
+                5,
+                OffsetDateTime::now_utc(),
+                &user.username,
+            )
+            .await
+            .unwrap();
+
+            Owned {
+                permission,
+                access_token,
+                share,
+                lock,
+                usage,
+                trashed,
+                version,
+            }
+        }
+
+        /// Check everything the user could only have for themselves is gone
+        async fn assert_personal_removed(&self) {
+            let (db, owned) = (&self.db, &self.owned);
+
+            assert_eq!(User::get(db, &self.user.username).await.unwrap(), None);
+            assert_eq!(
+                Permission::get(db, owned.permission.id).await.unwrap(),
+                None
+            );
+            assert_eq!(
+                AccessToken::get(db, owned.access_token.id).await.unwrap(),
+                None
+            );
+            assert_eq!(Lock::get(db, &owned.lock.token).await.unwrap(), None);
+
+            let group = Group::get(db, &self.user.username).await.unwrap().unwrap();
+            assert!(group.members(db).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_without_reassigning() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let (db, owned) = (&fixture.db, &fixture.owned);
+
+        let deletion = User::delete(db, &fixture.user.username, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            deletion,
+            UserDeletion {
+                permissions: 1,
+                access_tokens: 1,
+                shares: 1,
+                locks: 1,
+                group_memberships: 1,
+                reassigned_shares: 0,
+                reassigned_usage: 0,
+            }
+        );
+        fixture.assert_personal_removed().await;
+
+        assert_eq!(Share::get(db, owned.share.id).await.unwrap(), None);
+        for path in &owned.usage {
+            assert_eq!(FileUsage::get(db, path).await.unwrap(), None);
+        }
+
+        // What was trashed and replaced is kept without an owner
+        let trashed = TrashedItem::get(db, owned.trashed.id).await.unwrap();
+        assert_eq!(trashed.unwrap().deleted_by, None);
+        let version = FileVersion::get(db, owned.version.id).await.unwrap();
+        assert_eq!(version.unwrap().created_by, None);
+    }
+
+    #[tokio::test]
+    async fn delete_reassigning() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let (db, owned, other) = (&fixture.db, &fixture.owned, &fixture.other);
+
+        let deletion = User::delete(db, &fixture.user.username, Some(&other.username))
+            .await
+            .unwrap();
+        assert_eq!(
+            deletion,
+            UserDeletion {
+                permissions: 1,
+                access_tokens: 1,
+                shares: 0,
+                locks: 1,
+                group_memberships: 1,
+                reassigned_shares: 1,
+                reassigned_usage: 2,
+            }
+        );
+        fixture.assert_personal_removed().await;
+
+        let share = Share::get(db, owned.share.id).await.unwrap();
+        assert_eq!(share.unwrap().owner, other.username);
+        for path in &owned.usage {
+            let usage = FileUsage::get(db, path).await.unwrap();
+            assert_eq!(usage.unwrap().owner, other.username);
+        }
+        assert_eq!(FileUsage::total_for(db, &other.username).await.unwrap(), 20);
+
+        let trashed = TrashedItem::get(db, owned.trashed.id).await.unwrap();
+        assert_eq!(trashed.unwrap().deleted_by.as_ref(), Some(&other.username));
+        let version = FileVersion::get(db, owned.version.id).await.unwrap();
+        assert_eq!(version.unwrap().created_by.as_ref(), Some(&other.username));
+    }
+
+    #[tokio::test]
+    async fn failed_delete_changes_nothing() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let (db, owned) = (&fixture.db, &fixture.owned);
+
+        let missing = format!("{}-missing", fixture.user.username);
+        let result = User::delete(db, &fixture.user.username, Some(&missing)).await;
+        assert!(result.is_err());
+        let result = User::delete(db, &missing, None).await;
+        assert!(matches!(result, Err(Error::RowNotFound)));
+
+        assert!(User::get(db, &fixture.user.username)
+            .await
+            .unwrap()
+            .is_some());
+        let share = Share::get(db, owned.share.id).await.unwrap();
+        assert_eq!(share.unwrap().owner, fixture.user.username);
+        let usage = FileUsage::total_for(db, &fixture.user.username).await;
+        assert_eq!(usage.unwrap(), 20);
+    }
+
+    #[tokio::test]
+    async fn set_default_action_updates_user() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        let db = fixture.db.clone();
+
+        fixture
+            .user
+            .set_default_action(&db, Action::Admin)
+            .await
+            .unwrap();
+        assert_eq!(fixture.user.default_access, Action::Admin);
+
+        let stored = User::get(&db, &fixture.user.username).await.unwrap();
+        assert_eq!(stored.unwrap().default_access, Action::Admin);
+    }
 }
@@ -0,0 +1,117 @@
+use async_graphql::SimpleObject;
+use sqlx::{Error, PgPool, Result};
+use time::OffsetDateTime;
+
+/// A previous version of a file, kept when the file was overwritten
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct FileVersion {
+    pub id: i32,
+    pub path: String,
+    /// The name of the copy within the versions directory
+    #[graphql(skip)]
+    pub name: String,
+    pub size: i64,
+    /// When the content of this version was last modified
+    pub last_modified: OffsetDateTime,
+    /// The user who replaced this version
+    pub created_by: Option<String>,
+    /// When this version was replaced
+    pub created_at: OffsetDateTime,
+}
+
+impl FileVersion {
+    /// Get all the versions of a file, newest first
+    pub async fn list(db: &PgPool, path: &str) -> Result<Vec<FileVersion>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            FileVersion,
+            "SELECT id, path, name, size, last_modified, created_by, created_at FROM file_versions \
+            WHERE path = $1 ORDER BY created_at DESC, id DESC",
+            path
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Get all the versions created before the cutoff
+    pub async fn created_before(db: &PgPool, cutoff: OffsetDateTime) -> Result<Vec<FileVersion>> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            FileVersion,
+            "SELECT id, path, name, size, last_modified, created_by, created_at FROM file_versions \
+            WHERE created_at < $1",
+            cutoff
+        )
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    /// Find a version by its id
+    pub async fn get(db: &PgPool, id: i32) -> Result<Option<FileVersion>> {
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query_as!(
+            FileVersion,
+            "SELECT id, path, name, size, last_modified, created_by, created_at FROM file_versions \
+            WHERE id = $1",
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+
+        match result {
+            Ok(version) => Ok(Some(version)),
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record a new version of a file
+    pub async fn create(
+        db: &PgPool,
+        path: &str,
+        name: &str,
+        size: i64,
+        last_modified: OffsetDateTime,
+        created_by: &str,
+    ) -> Result<FileVersion> {
+        let mut conn = db.acquire().await?;
+        sqlx::query_as!(
+            FileVersion,
+            "INSERT INTO file_versions (path, name, size, last_modified, created_by) \
+            VALUES ($1, $2, $3, $4, $5) \
+            RETURNING id, path, name, size, last_modified, created_by, created_at",
+            path,
+            name,
+            size,
+            last_modified,
+            created_by
+        )
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    /// Forget about a version
+    pub async fn delete(db: &PgPool, id: i32) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!("DELETE FROM file_versions WHERE id = $1", id)
+            .execute(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Move the versions of a path and its children to a new location
+    pub async fn move_within(db: &PgPool, from: &str, to: &str) -> Result<()> {
+        let mut conn = db.acquire().await?;
+        sqlx::query!(
+            "UPDATE file_versions SET path = $2 || substr(path, length($1) + 1) \
+            WHERE path = $1 OR starts_with(path, $1 || '/')",
+            from,
+            to
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -12,6 +12,7 @@ use std::{
     fmt::{self, Display, Formatter},
     io::{self, ErrorKind},
 };
+use time::Duration;
 use tracing::error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -28,6 +29,10 @@ pub enum Error {
     BadRequest,
     /// For when the requested action is only valid on directories
     NotADirectory,
+    /// For when storing the data would exceed a quota
+    QuotaExceeded,
+    /// For when too many login attempts have failed, with how long until they are allowed again
+    TooManyRequests(Duration),
     /// Used when an unexpected and unhandleable error occurs
     /// i.e. database or file system errors
     Unexpected(Box<dyn StdError + Send + Sync>),
@@ -42,6 +47,12 @@ impl From<SqlxError> for Error {
     }
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Unexpected(Box::new(e))
+    }
+}
+
 impl From<InvalidMethod> for Error {
     fn from(_: InvalidMethod) -> Self {
         Error::BadRequest
@@ -65,6 +76,8 @@ impl Display for Error {
             Self::NotFound => write!(f, "not found"),
             Self::BadRequest => write!(f, "bad request"),
             Self::NotADirectory => write!(f, "path is not a directory"),
+            Self::QuotaExceeded => write!(f, "quota exceeded"),
+            Self::TooManyRequests(_) => write!(f, "too many failed login attempts"),
             Self::Unexpected(e) => {
                 error!(error = %e, source = ?e.source(), "an unexpected error occurred");
                 write!(f, "an unexpected error occurred")
@@ -73,6 +86,8 @@ impl Display for Error {
     }
 }
 
+impl StdError for Error {}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
@@ -90,6 +105,21 @@ impl IntoResponse for Error {
             Self::NotADirectory => {
                 static_response("path is not a directory", StatusCode::BAD_REQUEST)
             }
+            Self::QuotaExceeded => {
+                static_response("quota exceeded", StatusCode::INSUFFICIENT_STORAGE)
+            }
+            Self::TooManyRequests(retry_after) => {
+                let mut response = static_response(
+                    "too many failed login attempts",
+                    StatusCode::TOO_MANY_REQUESTS,
+                );
+                // Round up so clients never retry before the lockout ends
+                let seconds = retry_after.whole_seconds() + 1;
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+                response
+            }
             Self::Unexpected(e) => {
                 error!(error = %e, source = ?e.source(), "an unexpected error occurred");
                 static_response("internal server error", StatusCode::INTERNAL_SERVER_ERROR)
@@ -0,0 +1,796 @@
+use crate::{
+    database::{Action, Capabilities, Capability, Group, NewPermission, Permission, User},
+    error::{Error, Result},
+    security::validate_pattern,
+};
+use async_graphql::{Enum, SimpleObject};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+};
+use time::OffsetDateTime;
+
+/// The version of the document format produced by exports
+const VERSION: u32 = 1;
+
+/// The formats documents can be written in
+#[derive(Clone, Copy, Debug, Default, Enum, Eq, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Json,
+    Yaml,
+}
+
+/// All the users, groups and permissions of an instance
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Document {
+    pub version: u32,
+    #[serde(default)]
+    pub users: Vec<ExportedUser>,
+    #[serde(default)]
+    pub groups: Vec<ExportedGroup>,
+    #[serde(default)]
+    pub permissions: Vec<ExportedPermission>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedUser {
+    pub username: String,
+    pub name: String,
+    pub default_access: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedGroup {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub members: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedPermission {
+    /// The user the permission applies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// The group the permission applies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub path: String,
+    #[serde(default)]
+    pub pattern: bool,
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<Capability>>,
+    #[serde(default)]
+    pub affects_children: bool,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub valid_from: Option<OffsetDateTime>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub valid_until: Option<OffsetDateTime>,
+}
+
+impl ExportedPermission {
+    fn from_permission(permission: Permission) -> ExportedPermission {
+        ExportedPermission {
+            user: permission.applies_to,
+            group: permission.applies_to_group,
+            capabilities: permission
+                .custom_capabilities
+                .map(|capabilities| capabilities.iter().collect()),
+            path: permission.path,
+            pattern: permission.pattern,
+            action: permission.action,
+            affects_children: permission.affects_children,
+            valid_from: permission.valid_from,
+            valid_until: permission.valid_until,
+        }
+    }
+
+    fn to_new_permission(&self) -> NewPermission {
+        NewPermission {
+            path: self.path.clone(),
+            pattern: self.pattern,
+            action: self.action,
+            custom_capabilities: self.custom_capabilities(),
+            affects_children: self.affects_children,
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+        }
+    }
+
+    /// What identifies the permission among those of its user or group
+    fn key(&self) -> (&str, bool) {
+        (&self.path, self.pattern)
+    }
+
+    /// Check if the permission grants the same thing as another for the same path
+    fn same_grant(&self, other: &ExportedPermission) -> bool {
+        self.action == other.action
+            && self.custom_capabilities() == other.custom_capabilities()
+            && self.affects_children == other.affects_children
+            && self.valid_from == other.valid_from
+            && self.valid_until == other.valid_until
+    }
+
+    /// Compare capabilities as sets, so their order in the document does not matter
+    fn custom_capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+            .as_ref()
+            .map(|capabilities| capabilities.iter().copied().collect())
+    }
+}
+
+impl Display for ExportedPermission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.user, &self.group) {
+            (Some(user), _) => write!(f, "permission for user {user} on ")?,
+            (_, Some(group)) => write!(f, "permission for group {group} on ")?,
+            (None, None) => write!(f, "permission on ")?,
+        }
+
+        let kind = if self.pattern { "pattern " } else { "" };
+        write!(f, "{kind}{:?} ({:?}", self.path, self.action)?;
+        if let Some(capabilities) = &self.capabilities {
+            write!(f, ", capabilities {capabilities:?}")?;
+        }
+        if self.affects_children {
+            write!(f, ", affects children")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Document {
+    /// Serialize the document in the given format
+    pub fn to_string(&self, format: Format) -> std::result::Result<String, String> {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Parse and validate a document in the given format
+    pub fn parse(raw: &str, format: Format) -> std::result::Result<Document, String> {
+        let document: Document = match format {
+            Format::Json => serde_json::from_str(raw).map_err(|e| e.to_string())?,
+            Format::Yaml => serde_yaml::from_str(raw).map_err(|e| e.to_string())?,
+        };
+
+        document.validate()?;
+        Ok(document)
+    }
+
+    /// Check the document is consistent before importing it. Group members and permissions may
+    /// only refer to users and groups listed in the document.
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.version != VERSION {
+            return Err(format!("unsupported document version {}", self.version));
+        }
+
+        let mut usernames = HashSet::new();
+        for user in &self.users {
+            if user.username.is_empty() {
+                return Err(String::from("usernames cannot be empty"));
+            } else if !usernames.insert(&user.username) {
+                return Err(format!("user {} is listed more than once", user.username));
+            } else if matches!(user.quota, Some(quota) if quota < 0) {
+                return Err(format!(
+                    "quota for user {} cannot be negative",
+                    user.username
+                ));
+            }
+        }
+
+        let mut group_names = HashSet::new();
+        for group in &self.groups {
+            if group.name.is_empty() {
+                return Err(String::from("group names cannot be empty"));
+            } else if !group_names.insert(&group.name) {
+                return Err(format!("group {} is listed more than once", group.name));
+            }
+
+            if let Some(member) = group.members.iter().find(|m| !usernames.contains(m)) {
+                return Err(format!(
+                    "member {member} of group {} is not listed as a user",
+                    group.name
+                ));
+            }
+        }
+
+        let mut keys = HashSet::new();
+        for permission in &self.permissions {
+            if permission.user.is_some() == permission.group.is_some() {
+                return Err(format!(
+                    "{permission} must apply to exactly one of a user or group"
+                ));
+            } else if permission.pattern {
+                validate_pattern(&permission.path).map_err(|e| format!("{permission}: {e}"))?;
+            } else if permission.path.starts_with('/') || permission.path.ends_with('/') {
+                return Err(format!(
+                    "{permission}: path must not start or end with a slash"
+                ));
+            }
+
+            if matches!((permission.valid_from, permission.valid_until), (Some(from), Some(until)) if from >= until)
+            {
+                return Err(format!("{permission}: validity must start before it ends"));
+            }
+
+            let listed = match (&permission.user, &permission.group) {
+                (Some(user), _) => usernames.contains(user),
+                (_, Some(group)) => group_names.contains(group),
+                (None, None) => false,
+            };
+            if !listed {
+                return Err(format!(
+                    "{permission} applies to a user or group that is not listed"
+                ));
+            }
+
+            let target = (&permission.user, &permission.group);
+            if !keys.insert((target, permission.key())) {
+                return Err(format!("{permission} is listed more than once"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Collect all the users, groups and permissions into a document
+pub async fn export(db: &PgPool) -> Result<Document> {
+    let mut document = Document {
+        version: VERSION,
+        ..Document::default()
+    };
+
+    let mut users = User::list(db).await?;
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+    for user in users {
+        let mut permissions = user.permissions(db).await?;
+        permissions.sort_by(|a, b| a.path.cmp(&b.path).then(a.id.cmp(&b.id)));
+        document.permissions.extend(
+            permissions
+                .into_iter()
+                .map(ExportedPermission::from_permission),
+        );
+
+        document.users.push(ExportedUser {
+            username: user.username,
+            name: user.name,
+            default_access: user.default_access,
+            quota: user.quota,
+        });
+    }
+
+    let mut groups = Group::list(db).await?;
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    for group in groups {
+        let mut permissions = group.permissions(db).await?;
+        permissions.sort_by(|a, b| a.path.cmp(&b.path).then(a.id.cmp(&b.id)));
+        document.permissions.extend(
+            permissions
+                .into_iter()
+                .map(ExportedPermission::from_permission),
+        );
+
+        let members = group.members(db).await?;
+        document.groups.push(ExportedGroup {
+            members: members.into_iter().map(|user| user.username).collect(),
+            name: group.name,
+            description: group.description,
+        });
+    }
+
+    Ok(document)
+}
+
+/// The kind of change an import makes
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum ChangeKind {
+    Create,
+    Update,
+    Remove,
+}
+
+impl Display for ChangeKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Create => write!(f, "+"),
+            ChangeKind::Update => write!(f, "~"),
+            ChangeKind::Remove => write!(f, "-"),
+        }
+    }
+}
+
+/// A single change made, or that would be made, by an import
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub description: String,
+}
+
+/// The differences between a document and the database
+#[derive(Clone, Debug, Default, Eq, PartialEq, SimpleObject)]
+pub struct ImportReport {
+    /// Whether the changes were only reported, rather than applied
+    pub dry_run: bool,
+    pub changes: Vec<Change>,
+}
+
+impl ImportReport {
+    fn record(&mut self, kind: ChangeKind, description: String) {
+        self.changes.push(Change { kind, description });
+    }
+}
+
+/// Import a document, bringing the users and groups in it in line with the document. Users and
+/// groups that are not in the document are left alone, while the permissions and members of those
+/// that are in it are replaced by the document's. Importing the same document again makes no
+/// further changes. When `dry_run` is set, the changes are only reported.
+pub async fn import(db: &PgPool, document: &Document, dry_run: bool) -> Result<ImportReport> {
+    // Everything is imported in a single transaction, so a failure part way through changes nothing
+    let mut tx = db.begin().await?;
+    let mut report = ImportReport {
+        dry_run,
+        ..ImportReport::default()
+    };
+
+    // Users
+    let current = User::list(&mut *tx).await?;
+    let current = current
+        .into_iter()
+        .map(|user| (user.username.clone(), user))
+        .collect::<HashMap<_, _>>();
+    for desired in &document.users {
+        match current.get(&desired.username) {
+            None => report.record(ChangeKind::Create, format!("user {}", desired.username)),
+            Some(existing) => {
+                let mut differences = Vec::new();
+                if existing.name != desired.name {
+                    differences.push(format!("name {:?} -> {:?}", existing.name, desired.name));
+                }
+                if existing.default_access != desired.default_access {
+                    differences.push(format!(
+                        "default access {:?} -> {:?}",
+                        existing.default_access, desired.default_access
+                    ));
+                }
+                if existing.quota != desired.quota {
+                    differences.push(format!("quota {:?} -> {:?}", existing.quota, desired.quota));
+                }
+
+                if differences.is_empty() {
+                    continue;
+                }
+                report.record(
+                    ChangeKind::Update,
+                    format!("user {}: {}", desired.username, differences.join(", ")),
+                );
+            }
+        }
+
+        if !dry_run {
+            let mut user =
+                User::create_if_not_exists(&mut *tx, &desired.username, &desired.name).await?;
+            if user.default_access != desired.default_access {
+                user.set_default_action(&mut *tx, desired.default_access)
+                    .await?;
+            }
+            if user.quota != desired.quota {
+                user.set_quota(&mut *tx, desired.quota).await?;
+            }
+        }
+    }
+
+    // Groups and their members
+    let current = Group::list(&mut *tx).await?;
+    let current = current
+        .into_iter()
+        .map(|group| (group.name.clone(), group))
+        .collect::<HashMap<_, _>>();
+    for desired in &document.groups {
+        let existing = current.get(&desired.name);
+        let members = match existing {
+            Some(group) => group.members(&mut *tx).await?,
+            None => Vec::new(),
+        };
+        let members = members
+            .into_iter()
+            .map(|user| user.username)
+            .collect::<BTreeSet<_>>();
+
+        match existing {
+            None => report.record(ChangeKind::Create, format!("group {}", desired.name)),
+            Some(group) if group.description != desired.description => report.record(
+                ChangeKind::Update,
+                format!(
+                    "group {}: description {:?} -> {:?}",
+                    desired.name, group.description, desired.description
+                ),
+            ),
+            Some(_) => {}
+        }
+
+        let added = desired.members.difference(&members).collect::<Vec<_>>();
+        let removed = members.difference(&desired.members).collect::<Vec<_>>();
+        for username in &added {
+            report.record(
+                ChangeKind::Create,
+                format!("member {username} of group {}", desired.name),
+            );
+        }
+        for username in &removed {
+            report.record(
+                ChangeKind::Remove,
+                format!("member {username} of group {}", desired.name),
+            );
+        }
+
+        if !dry_run {
+            let mut group = match existing {
+                Some(group) => group.clone(),
+                None => Group::create(&mut *tx, &desired.name, &desired.description)
+                    .await?
+                    .ok_or(Error::NotFound)?,
+            };
+            if group.description != desired.description {
+                group
+                    .set_description(&mut *tx, desired.description.clone())
+                    .await?;
+            }
+            for username in added {
+                group.add_member(&mut *tx, username).await?;
+            }
+            for username in removed {
+                group.remove_member(&mut *tx, username).await?;
+            }
+        }
+    }
+
+    // Permissions for the users and groups in the document
+    for user in &document.users {
+        let target = User::get(&mut *tx, &user.username).await?;
+        let existing = match &target {
+            Some(target) => target.permissions(&mut *tx).await?,
+            None => Vec::new(),
+        };
+        let desired = document
+            .permissions
+            .iter()
+            .filter(|permission| permission.user.as_ref() == Some(&user.username));
+
+        for change in diff_permissions(existing, desired) {
+            report.record(change.kind(), change.to_string());
+            if dry_run {
+                continue;
+            }
+
+            match change {
+                PermissionChange::Assign(permission, _) => {
+                    let target = target.as_ref().ok_or(Error::NotFound)?;
+                    target
+                        .assign_permission(&mut *tx, &permission.to_new_permission())
+                        .await?;
+                }
+                PermissionChange::Remove(id, _) => Permission::delete(&mut *tx, id).await?,
+            }
+        }
+    }
+    for group in &document.groups {
+        let target = Group::get(&mut *tx, &group.name).await?;
+        let existing = match &target {
+            Some(target) => target.permissions(&mut *tx).await?,
+            None => Vec::new(),
+        };
+        let desired = document
+            .permissions
+            .iter()
+            .filter(|permission| permission.group.as_ref() == Some(&group.name));
+
+        for change in diff_permissions(existing, desired) {
+            report.record(change.kind(), change.to_string());
+            if dry_run {
+                continue;
+            }
+
+            match change {
+                PermissionChange::Assign(permission, _) => {
+                    let target = target.as_ref().ok_or(Error::NotFound)?;
+                    target
+                        .assign_permission(&mut *tx, &permission.to_new_permission())
+                        .await?;
+                }
+                PermissionChange::Remove(id, _) => Permission::delete(&mut *tx, id).await?,
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// A change needed to bring the permissions of a user or group in line with a document
+enum PermissionChange<'d> {
+    /// Assign the permission, replacing the existing one for the same path if it exists
+    Assign(&'d ExportedPermission, ChangeKind),
+    /// Remove the permission with the ID
+    Remove(i32, ExportedPermission),
+}
+
+impl PermissionChange<'_> {
+    fn kind(&self) -> ChangeKind {
+        match self {
+            PermissionChange::Assign(_, kind) => *kind,
+            PermissionChange::Remove(..) => ChangeKind::Remove,
+        }
+    }
+}
+
+impl Display for PermissionChange<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PermissionChange::Assign(permission, _) => write!(f, "{permission}"),
+            PermissionChange::Remove(_, permission) => write!(f, "{permission}"),
+        }
+    }
+}
+
+/// Find the changes needed to turn the existing permissions into the desired ones
+fn diff_permissions<'d>(
+    existing: Vec<Permission>,
+    desired: impl Iterator<Item = &'d ExportedPermission>,
+) -> Vec<PermissionChange<'d>> {
+    let mut existing = existing
+        .into_iter()
+        .map(|permission| {
+            let id = permission.id;
+            (id, ExportedPermission::from_permission(permission))
+        })
+        .collect::<Vec<_>>();
+
+    let mut changes = Vec::new();
+    for permission in desired {
+        let position = existing
+            .iter()
+            .position(|(_, current)| current.key() == permission.key());
+        match position.map(|position| existing.swap_remove(position)) {
+            None => changes.push(PermissionChange::Assign(permission, ChangeKind::Create)),
+            Some((_, current)) if !current.same_grant(permission) => {
+                changes.push(PermissionChange::Assign(permission, ChangeKind::Update))
+            }
+            Some(_) => {}
+        }
+    }
+
+    existing.sort_by_key(|(id, _)| *id);
+    changes.extend(
+        existing
+            .into_iter()
+            .map(|(id, permission)| PermissionChange::Remove(id, permission)),
+    );
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        diff_permissions, ChangeKind, Document, ExportedPermission, Format, PermissionChange,
+    };
+    use crate::database::{Action, Permission};
+
+    const DOCUMENT: &str = r#"{
+        "version": 1,
+        "users": [
+            { "username": "alice", "name": "Alice", "default_access": "admin" },
+            { "username": "bob", "name": "Bob", "default_access": "read", "quota": 1024 }
+        ],
+        "groups": [
+            { "name": "contractors", "members": ["bob"] }
+        ],
+        "permissions": [
+            { "user": "bob", "path": "projects", "action": "modify", "affects_children": true },
+            {
+                "group": "contractors",
+                "path": "**/*.key",
+                "pattern": true,
+                "action": "deny",
+                "valid_until": "2030-01-01T00:00:00Z"
+            },
+            { "user": "bob", "path": "inbox", "action": "deny", "capabilities": ["create"] }
+        ]
+    }"#;
+
+    macro_rules! invalid {
+        ($raw:expr) => {
+            Document::parse(&$raw, Format::Json).unwrap_err()
+        };
+    }
+
+    #[test]
+    fn round_trip() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        assert_eq!(document.users.len(), 2);
+        assert_eq!(document.permissions.len(), 3);
+
+        for format in [Format::Json, Format::Yaml] {
+            let serialized = document.to_string(format).unwrap();
+            assert_eq!(Document::parse(&serialized, format).unwrap(), document);
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let error = invalid!(DOCUMENT.replace(r#""version": 1"#, r#""version": 2"#));
+        assert!(error.contains("version"), "{error}");
+    }
+
+    #[test]
+    fn unlisted_member() {
+        let error = invalid!(DOCUMENT.replace(r#"["bob"]"#, r#"["carol"]"#));
+        assert!(error.contains("carol"), "{error}");
+    }
+
+    #[test]
+    fn unlisted_permission_target() {
+        let error = invalid!(DOCUMENT.replace(
+            r#""user": "bob", "path": "inbox""#,
+            r#""user": "carol", "path": "inbox""#
+        ));
+        assert!(error.contains("not listed"), "{error}");
+    }
+
+    #[test]
+    fn duplicate_permission() {
+        let error = invalid!(DOCUMENT
+            .replace(r#""path": "inbox""#, r#""path": "projects""#)
+            .replace(
+                r#""capabilities": ["create"]"#,
+                r#""capabilities": ["create"], "affects_children": true"#
+            ));
+        assert!(error.contains("more than once"), "{error}");
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let error = invalid!(DOCUMENT.replace("**/*.key", "**.key"));
+        assert!(error.contains("`**`"), "{error}");
+    }
+
+    /// The permissions of the user and group in the document, as if they were already assigned
+    fn assigned(document: &Document) -> Vec<Permission> {
+        document
+            .permissions
+            .iter()
+            .zip(1..)
+            .map(|(permission, id)| {
+                let new = permission.to_new_permission();
+                Permission {
+                    id,
+                    applies_to: permission.user.clone(),
+                    applies_to_group: permission.group.clone(),
+                    path: new.path,
+                    pattern: new.pattern,
+                    action: new.action,
+                    custom_capabilities: new.custom_capabilities,
+                    affects_children: new.affects_children,
+                    valid_from: new.valid_from,
+                    valid_until: new.valid_until,
+                }
+            })
+            .collect()
+    }
+
+    fn summarize(changes: Vec<PermissionChange<'_>>) -> Vec<(ChangeKind, String)> {
+        changes
+            .into_iter()
+            .map(|change| match &change {
+                PermissionChange::Assign(permission, kind) => (*kind, permission.path.clone()),
+                PermissionChange::Remove(id, permission) => {
+                    (ChangeKind::Remove, format!("{id}:{}", permission.path))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        let changes = diff_permissions(assigned(&document), document.permissions.iter());
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn diff_creates_missing() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        let changes = diff_permissions(Vec::new(), document.permissions.iter());
+        assert_eq!(
+            summarize(changes),
+            vec![
+                (ChangeKind::Create, String::from("projects")),
+                (ChangeKind::Create, String::from("**/*.key")),
+                (ChangeKind::Create, String::from("inbox")),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_updates_changed_grants() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        let desired = [
+            ExportedPermission {
+                action: Action::Read,
+                ..document.permissions[0].clone()
+            },
+            ExportedPermission {
+                affects_children: true,
+                ..document.permissions[1].clone()
+            },
+            ExportedPermission {
+                capabilities: None,
+                ..document.permissions[2].clone()
+            },
+        ];
+
+        let changes = diff_permissions(assigned(&document), desired.iter());
+        assert_eq!(
+            summarize(changes),
+            vec![
+                (ChangeKind::Update, String::from("projects")),
+                (ChangeKind::Update, String::from("**/*.key")),
+                (ChangeKind::Update, String::from("inbox")),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_removes_unlisted() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        let desired = [document.permissions[1].clone()];
+
+        let changes = diff_permissions(assigned(&document), desired.iter());
+        assert_eq!(
+            summarize(changes),
+            vec![
+                (ChangeKind::Remove, String::from("1:projects")),
+                (ChangeKind::Remove, String::from("3:inbox")),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_pattern_is_part_of_key() {
+        let document = Document::parse(DOCUMENT, Format::Json).unwrap();
+        let desired = [ExportedPermission {
+            pattern: true,
+            ..document.permissions[0].clone()
+        }];
+
+        let changes = diff_permissions(assigned(&document)[..1].to_vec(), desired.iter());
+        assert_eq!(
+            summarize(changes),
+            vec![
+                (ChangeKind::Create, String::from("projects")),
+                (ChangeKind::Remove, String::from("1:projects")),
+            ]
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    security::Visibility,
+};
 use async_graphql::{Enum, SimpleObject};
 use std::{
     fs::FileType,
@@ -9,8 +12,8 @@ use time::OffsetDateTime;
 use tokio::fs;
 use tokio_stream::{wrappers::ReadDirStream, StreamExt};
 
-/// Get a list of all items specified directory
-pub async fn list(base: &Path, path: PathBuf) -> Result<Vec<Entry>> {
+/// Get a list of all items specified directory that are visible to the user
+pub async fn list(base: &Path, path: PathBuf, visibility: &Visibility) -> Result<Vec<Entry>> {
     let full_path = base.join(&path);
     if !full_path.exists() {
         return Err(Error::NotFound);
@@ -26,17 +29,22 @@ pub async fn list(base: &Path, path: PathBuf) -> Result<Vec<Entry>> {
 
     while let Some(entry) = stream.next().await {
         let entry = entry?;
-        let meta = entry.metadata().await?;
 
-        // Extract information about the entry
         let name = entry.file_name().to_string_lossy().into_owned();
+        let entry_path = path.join(&name);
+        if !visibility.is_visible(&entry_path) {
+            continue;
+        }
+
+        // Extract information about the entry
+        let meta = entry.metadata().await?;
         let created_at = meta.created().unwrap_or(SystemTime::UNIX_EPOCH).into();
         let last_modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH).into();
 
         // Add the entry
         entries.push(Entry {
             kind: meta.file_type().into(),
-            path: path.join(&name).display().to_string(),
+            path: entry_path.display().to_string(),
             name,
             created_at,
             last_modified,
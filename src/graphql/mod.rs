@@ -1,4 +1,8 @@
-use crate::{config::Config, database::User};
+use crate::{
+    config::Config,
+    database::User,
+    security::{credentials::CredentialCache, throttle::Throttle, PermissionCache},
+};
 use async_graphql::{extensions, EmptySubscription, Schema as BaseSchema};
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::Extension;
@@ -15,10 +19,19 @@ mod tracing;
 type Schema = BaseSchema<query::Query, mutation::Mutation, EmptySubscription>;
 
 /// Build the schema for the GraphQL handler
-pub fn schema(config: Arc<Config>, db: PgPool) -> Schema {
+pub fn schema(
+    config: Arc<Config>,
+    db: PgPool,
+    throttle: Arc<Throttle>,
+    credentials: Arc<CredentialCache>,
+    permissions: Arc<PermissionCache>,
+) -> Schema {
     Schema::build(query::Query, mutation::Mutation, EmptySubscription)
         .data(config)
         .data(db)
+        .data(throttle)
+        .data(credentials)
+        .data(permissions)
         .extension(extensions::Analyzer)
         .extension(tracing::Tracing)
         .extension(logging::Logger)
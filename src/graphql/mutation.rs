@@ -1,11 +1,28 @@
 use super::outputs::*;
 use crate::{
-    database::{Action, Permission, User},
+    config::Config,
+    database::{
+        AccessToken, Action, Capability, FileVersion, FolderQuota, Group, GroupMapping, Lock,
+        Permission, Share, TrashedItem, User,
+    },
     error::Error,
+    export::{self, Document, Format, ImportReport},
+    security::{
+        check_permissions,
+        credentials::CredentialCache,
+        new_permission, normalize_path, sanitize_path,
+        throttle::{Lockout, LockoutKind, Throttle},
+        PermissionCache,
+    },
+    trash, versions, webdav,
 };
-use async_graphql::{Context, Error as GraphQLError, Object, Result};
+use async_graphql::{Context, Error as GraphQLError, MaybeUndefined, Object, Result};
 use sqlx::PgPool;
-use std::path::{Component, Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use time::OffsetDateTime;
 
 pub struct Mutation;
 
@@ -19,9 +36,67 @@ impl Mutation {
         let db = ctx.data::<PgPool>()?;
 
         let token = user.regenerate_access_token(db).await?;
+        ctx.data::<Arc<CredentialCache>>()?
+            .invalidate(&user.username);
+
         Ok(RegenerateAccessTokenResult { token })
     }
 
+    async fn create_access_token(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        path: Option<String>,
+        action: Option<Action>,
+        expires_at: Option<OffsetDateTime>,
+    ) -> Result<CreateAccessTokenResult> {
+        let user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let name = name.trim();
+        let expired = matches!(expires_at, Some(e) if e <= OffsetDateTime::now_utc());
+        if name.is_empty() {
+            return Err(GraphQLError::new("name cannot be empty"));
+        } else if expired {
+            return Err(GraphQLError::new("expiry must be in the future"));
+        }
+
+        let path = path.as_deref().map(normalize_path);
+        let (access_token, token) = user
+            .create_access_token(db, name, path.as_deref(), action, expires_at)
+            .await?
+            .ok_or_else(|| GraphQLError::new("an access token with that name already exists"))?;
+
+        Ok(CreateAccessTokenResult {
+            token,
+            access_token,
+        })
+    }
+
+    async fn remove_access_token(
+        &self,
+        ctx: &Context<'_>,
+        token_id: i32,
+    ) -> Result<DeleteResult<i32, AccessToken>> {
+        let current_user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let access_token = AccessToken::get(db, token_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        // Users can always remove their own tokens
+        if access_token.owner != current_user.username && !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        AccessToken::delete(db, token_id).await?;
+        ctx.data::<Arc<CredentialCache>>()?
+            .invalidate(&access_token.owner);
+
+        Ok(DeleteResult::new(token_id))
+    }
+
     async fn revoke_access_token(&self, ctx: &Context<'_>, username: String) -> Result<User> {
         let user = ctx.data::<User>()?;
         if !user.is_admin() {
@@ -31,11 +106,182 @@ impl Mutation {
         let db = ctx.data::<PgPool>()?;
 
         let user = User::get(db, &username).await?.ok_or(Error::NotFound)?;
-        user.revoke_access_token(db).await?;
+        user.revoke_access_tokens(db).await?;
+        ctx.data::<Arc<CredentialCache>>()?
+            .invalidate(&user.username);
 
         Ok(user.clone())
     }
 
+    async fn create_share(
+        &self,
+        ctx: &Context<'_>,
+        path: String,
+        password: Option<String>,
+        expires_at: Option<OffsetDateTime>,
+        max_downloads: Option<i32>,
+    ) -> Result<Share> {
+        let config = ctx.data::<Arc<Config>>()?;
+        let user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let expired = matches!(expires_at, Some(e) if e <= OffsetDateTime::now_utc());
+        if expired {
+            return Err(GraphQLError::new("expiry must be in the future"));
+        } else if matches!(max_downloads, Some(max) if max < 1) {
+            return Err(GraphQLError::new("download limit must be at least 1"));
+        }
+
+        // Users can only share what they are allowed to share
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        let sanitized = sanitize_path(PathBuf::from(path))?;
+        check_permissions(db, cache, user, &sanitized, Capability::Share).await?;
+        if !tokio::fs::try_exists(config.path.join(&sanitized)).await? {
+            return Err(Error::NotFound.into());
+        }
+
+        let share = user
+            .create_share(
+                db,
+                &sanitized.display().to_string(),
+                password.as_deref().filter(|p| !p.is_empty()),
+                expires_at,
+                max_downloads,
+            )
+            .await?;
+        Ok(share)
+    }
+
+    async fn revoke_share(
+        &self,
+        ctx: &Context<'_>,
+        share_id: i32,
+    ) -> Result<DeleteResult<i32, Share>> {
+        let current_user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let share = Share::get(db, share_id).await?.ok_or(Error::NotFound)?;
+
+        // Users can always revoke their own shares
+        if share.owner != current_user.username && !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        Share::delete(db, share_id).await?;
+
+        Ok(DeleteResult::new(share_id))
+    }
+
+    async fn release_lock(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+    ) -> Result<DeleteResult<String, Lock>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let lock = Lock::get(db, &token).await?.ok_or(Error::NotFound)?;
+        Lock::delete(db, &lock.token).await?;
+
+        Ok(DeleteResult::new(lock.token))
+    }
+
+    async fn restore_version(&self, ctx: &Context<'_>, version_id: i32) -> Result<FileVersion> {
+        let config = ctx.data::<Arc<Config>>()?;
+        let user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let version = FileVersion::get(db, version_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        check_permissions(
+            db,
+            cache,
+            user,
+            Path::new(&version.path),
+            Capability::Overwrite,
+        )
+        .await?;
+
+        versions::restore(db, config, user, &version).await?;
+        Ok(version)
+    }
+
+    async fn restore_from_trash(&self, ctx: &Context<'_>, item_id: i32) -> Result<TrashedItem> {
+        let config = ctx.data::<Arc<Config>>()?;
+        let current_user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let item = TrashedItem::get(db, item_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        // Users can restore what they deleted, as long as they can still create at the location
+        if item.deleted_by.as_ref() != Some(&current_user.username) && !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        check_permissions(
+            db,
+            cache,
+            current_user,
+            Path::new(&item.original_path),
+            Capability::Create,
+        )
+        .await?;
+
+        if !trash::restore(db, &config.path, &item).await? {
+            return Err(GraphQLError::new(
+                "an item already exists at the original location",
+            ));
+        }
+
+        Ok(item)
+    }
+
+    async fn purge_from_trash(
+        &self,
+        ctx: &Context<'_>,
+        item_id: i32,
+    ) -> Result<DeleteResult<i32, TrashedItem>> {
+        let config = ctx.data::<Arc<Config>>()?;
+        let current_user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        let item = TrashedItem::get(db, item_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        // Users can always purge what they deleted
+        if item.deleted_by.as_ref() != Some(&current_user.username) && !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        trash::purge(db, &config.path, &item).await?;
+
+        Ok(DeleteResult::new(item_id))
+    }
+
+    async fn empty_trash(&self, ctx: &Context<'_>) -> Result<Vec<TrashedItem>> {
+        let config = ctx.data::<Arc<Config>>()?;
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let items = TrashedItem::list(db).await?;
+        for item in &items {
+            trash::purge(db, &config.path, item).await?;
+        }
+
+        Ok(items)
+    }
+
     async fn update_default_permission(
         &self,
         ctx: &Context<'_>,
@@ -52,31 +298,163 @@ impl Mutation {
         // Update the user
         let mut user = User::get(db, &user).await?.ok_or(Error::NotFound)?;
         user.set_default_action(db, action).await?;
+        ctx.data::<Arc<PermissionCache>>()?
+            .invalidate(&user.username);
+
+        Ok(user)
+    }
+
+    async fn update_user_quota(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        quota: Option<i64>,
+    ) -> Result<User> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        if matches!(quota, Some(q) if q < 0) {
+            return Err(GraphQLError::new("quota cannot be negative"));
+        }
+
+        let db = ctx.data::<PgPool>()?;
+
+        let mut user = User::get(db, &user).await?.ok_or(Error::NotFound)?;
+        user.set_quota(db, quota).await?;
+        ctx.data::<Arc<PermissionCache>>()?
+            .invalidate(&user.username);
 
         Ok(user)
     }
 
-    async fn delete_user(&self, ctx: &Context<'_>, user: String) -> Result<DeleteResult<String>> {
+    async fn set_folder_quota(
+        &self,
+        ctx: &Context<'_>,
+        folder: String,
+        quota: i64,
+    ) -> Result<FolderQuota> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        // Quotas can only be placed on folders directly within the served directory
+        let folder = sanitize_path(PathBuf::from(folder))?
+            .to_string_lossy()
+            .into_owned();
+        if folder.is_empty() || folder.contains('/') {
+            return Err(GraphQLError::new(
+                "quotas can only be set on top-level folders",
+            ));
+        } else if quota < 0 {
+            return Err(GraphQLError::new("quota cannot be negative"));
+        }
+
+        // Count what is already stored, later changes are tracked as they are made
+        let config = ctx.data::<Arc<Config>>()?;
+        let used = webdav::disk_usage(&config.path.join(&folder)).await?;
+
+        let db = ctx.data::<PgPool>()?;
+        let quota = FolderQuota::set(db, &folder, quota, used as i64).await?;
+
+        Ok(quota)
+    }
+
+    async fn remove_folder_quota(
+        &self,
+        ctx: &Context<'_>,
+        folder: String,
+    ) -> Result<DeleteResult<String, FolderQuota>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let folder = sanitize_path(PathBuf::from(folder))?
+            .to_string_lossy()
+            .into_owned();
+        FolderQuota::delete(db, &folder).await?;
+
+        Ok(DeleteResult::new(folder))
+    }
+
+    async fn clear_lockout(
+        &self,
+        ctx: &Context<'_>,
+        kind: LockoutKind,
+        key: String,
+    ) -> Result<DeleteResult<String, Lockout>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let throttle = ctx.data::<Arc<Throttle>>()?;
+        if !throttle.clear(kind, &key) {
+            return Err(Error::NotFound.into());
+        }
+
+        Ok(DeleteResult::new(key))
+    }
+
+    async fn delete_user(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        reassign_to: Option<String>,
+    ) -> Result<UserDeleteResult> {
         let current_user = ctx.data::<User>()?;
         if !current_user.is_admin() {
             return Err(Error::InvalidPermissions.into());
         } else if current_user.username == user {
             return Err(GraphQLError::new("cannot delete yourself"));
+        } else if reassign_to.as_ref() == Some(&user) {
+            return Err(GraphQLError::new(
+                "cannot reassign to the user being deleted",
+            ));
         }
 
         let db = ctx.data::<PgPool>()?;
-        User::delete(db, &user).await?;
+        if let Some(new_owner) = &reassign_to {
+            if User::get(db, new_owner).await?.is_none() {
+                return Err(GraphQLError::new("user to reassign to does not exist"));
+            }
+        }
+
+        let deletion = User::delete(db, &user, reassign_to.as_deref())
+            .await
+            .map_err(Error::from)?;
+        ctx.data::<Arc<CredentialCache>>()?.invalidate(&user);
+        ctx.data::<Arc<PermissionCache>>()?.invalidate(&user);
 
-        Ok(DeleteResult { last_removed: user })
+        Ok(UserDeleteResult {
+            last_removed: user,
+            reassigned_to: reassign_to,
+            removed_permissions: deletion.permissions as i32,
+            removed_access_tokens: deletion.access_tokens as i32,
+            removed_shares: deletion.shares as i32,
+            removed_locks: deletion.locks as i32,
+            removed_group_memberships: deletion.group_memberships as i32,
+            reassigned_shares: deletion.reassigned_shares as i32,
+            reassigned_usage: deletion.reassigned_usage as i32,
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn assign_permission_to_user(
         &self,
         ctx: &Context<'_>,
         user: String,
         path: String,
+        #[graphql(default)] pattern: bool,
         action: Action,
+        capabilities: Option<Vec<Capability>>,
         affects_children: bool,
+        valid_from: Option<OffsetDateTime>,
+        valid_until: Option<OffsetDateTime>,
     ) -> Result<Permission> {
         let db = ctx.data::<PgPool>()?;
 
@@ -85,27 +463,304 @@ impl Mutation {
             return Err(Error::InvalidPermissions.into());
         }
 
-        // Remove any leading and trailing slashes
-        let path = Path::new(&path)
-            .components()
-            .filter(|&c| c != Component::RootDir)
-            .collect::<PathBuf>()
-            .display()
-            .to_string();
+        let permission = new_permission(
+            &path,
+            pattern,
+            action,
+            capabilities,
+            affects_children,
+            valid_from,
+            valid_until,
+        )
+        .map_err(GraphQLError::new)?;
 
         // Assign the permission
         let user = User::get(db, &user).await?.ok_or(Error::NotFound)?;
-        let permission = user
-            .assign_permission(db, &path, action, affects_children)
-            .await?;
+        let permission = user.assign_permission(db, &permission).await?;
+        ctx.data::<Arc<PermissionCache>>()?
+            .invalidate(&user.username);
+
         Ok(permission)
     }
 
+    async fn create_group(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        description: Option<String>,
+    ) -> Result<Group> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(GraphQLError::new("name cannot be empty"));
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let group = Group::create(db, name, description.as_deref().unwrap_or_default())
+            .await?
+            .ok_or_else(|| GraphQLError::new("a group with that name already exists"))?;
+
+        Ok(group)
+    }
+
+    async fn update_group(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        description: String,
+    ) -> Result<Group> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+
+        let mut group = Group::get(db, &name).await?.ok_or(Error::NotFound)?;
+        group.set_description(db, description).await?;
+
+        Ok(group)
+    }
+
+    async fn delete_group(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+    ) -> Result<DeleteResult<String, Group>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        Group::delete(db, &name).await?;
+        ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
+
+        Ok(DeleteResult::new(name))
+    }
+
+    async fn add_user_to_group(
+        &self,
+        ctx: &Context<'_>,
+        group: String,
+        user: String,
+    ) -> Result<Group> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+
+        let group = Group::get(db, &group).await?.ok_or(Error::NotFound)?;
+        let user = User::get(db, &user).await?.ok_or(Error::NotFound)?;
+        group.add_member(db, &user.username).await?;
+        ctx.data::<Arc<PermissionCache>>()?
+            .invalidate(&user.username);
+
+        Ok(group)
+    }
+
+    async fn remove_user_from_group(
+        &self,
+        ctx: &Context<'_>,
+        group: String,
+        user: String,
+    ) -> Result<Group> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+
+        let group = Group::get(db, &group).await?.ok_or(Error::NotFound)?;
+        group.remove_member(db, &user).await?;
+        ctx.data::<Arc<PermissionCache>>()?.invalidate(&user);
+
+        Ok(group)
+    }
+
+    async fn set_group_mapping(
+        &self,
+        ctx: &Context<'_>,
+        external_group: String,
+        default_access: Option<Action>,
+        group: Option<String>,
+    ) -> Result<GroupMapping> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        if external_group.is_empty() {
+            return Err(GraphQLError::new("external group cannot be empty"));
+        } else if default_access.is_none() && group.is_none() {
+            return Err(GraphQLError::new(
+                "a mapping must grant a default access or a group",
+            ));
+        }
+
+        let db = ctx.data::<PgPool>()?;
+
+        if let Some(group) = &group {
+            Group::get(db, group).await?.ok_or(Error::NotFound)?;
+        }
+
+        let mapping =
+            GroupMapping::set(db, &external_group, default_access, group.as_deref()).await?;
+
+        // Users need to login again for the mapping to apply
+        ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
+
+        Ok(mapping)
+    }
+
+    async fn remove_group_mapping(
+        &self,
+        ctx: &Context<'_>,
+        external_group: String,
+    ) -> Result<DeleteResult<String, GroupMapping>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        GroupMapping::delete(db, &external_group).await?;
+        ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
+
+        Ok(DeleteResult::new(external_group))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn assign_permission_to_group(
+        &self,
+        ctx: &Context<'_>,
+        group: String,
+        path: String,
+        #[graphql(default)] pattern: bool,
+        action: Action,
+        capabilities: Option<Vec<Capability>>,
+        affects_children: bool,
+        valid_from: Option<OffsetDateTime>,
+        valid_until: Option<OffsetDateTime>,
+    ) -> Result<Permission> {
+        let db = ctx.data::<PgPool>()?;
+
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let permission = new_permission(
+            &path,
+            pattern,
+            action,
+            capabilities,
+            affects_children,
+            valid_from,
+            valid_until,
+        )
+        .map_err(GraphQLError::new)?;
+
+        // Assign the permission
+        let group = Group::get(db, &group).await?.ok_or(Error::NotFound)?;
+        let permission = group.assign_permission(db, &permission).await?;
+        ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
+
+        Ok(permission)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_permission(
+        &self,
+        ctx: &Context<'_>,
+        permission_id: i32,
+        path: Option<String>,
+        pattern: Option<bool>,
+        action: Option<Action>,
+        capabilities: MaybeUndefined<Vec<Capability>>,
+        affects_children: Option<bool>,
+        valid_from: MaybeUndefined<OffsetDateTime>,
+        valid_until: MaybeUndefined<OffsetDateTime>,
+    ) -> Result<Permission> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let existing = Permission::get(db, permission_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        // Anything not provided is kept as-is
+        let capabilities = match capabilities {
+            MaybeUndefined::Undefined => existing
+                .custom_capabilities
+                .map(|capabilities| capabilities.iter().collect()),
+            MaybeUndefined::Null => None,
+            MaybeUndefined::Value(capabilities) => Some(capabilities),
+        };
+        let (mut from, mut until) = (existing.valid_from, existing.valid_until);
+        valid_from.update_to(&mut from);
+        valid_until.update_to(&mut until);
+
+        let permission = new_permission(
+            path.as_deref().unwrap_or(&existing.path),
+            pattern.unwrap_or(existing.pattern),
+            action.unwrap_or(existing.action),
+            capabilities,
+            affects_children.unwrap_or(existing.affects_children),
+            from,
+            until,
+        )
+        .map_err(GraphQLError::new)?;
+
+        let updated = Permission::update(db, permission_id, &permission).await?;
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        match &updated.applies_to {
+            Some(user) => cache.invalidate(user),
+            None => cache.invalidate_all(),
+        }
+
+        Ok(updated)
+    }
+
+    async fn import_configuration(
+        &self,
+        ctx: &Context<'_>,
+        document: String,
+        #[graphql(default)] format: Format,
+        #[graphql(default)] dry_run: bool,
+    ) -> Result<ImportReport> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let document = Document::parse(&document, format)
+            .map_err(|e| GraphQLError::new(format!("invalid document: {e}")))?;
+
+        let db = ctx.data::<PgPool>()?;
+        let report = export::import(db, &document, dry_run).await?;
+        if !dry_run {
+            ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
+        }
+
+        Ok(report)
+    }
+
     async fn remove_permission(
         &self,
         ctx: &Context<'_>,
         permission_id: i32,
-    ) -> Result<DeleteResult<i32>> {
+    ) -> Result<DeleteResult<i32, Permission>> {
         let current_user = ctx.data::<User>()?;
         if !current_user.is_admin() {
             return Err(Error::InvalidPermissions.into());
@@ -113,9 +768,8 @@ impl Mutation {
 
         let db = ctx.data::<PgPool>()?;
         Permission::delete(db, permission_id).await?;
+        ctx.data::<Arc<PermissionCache>>()?.invalidate_all();
 
-        Ok(DeleteResult {
-            last_removed: permission_id,
-        })
+        Ok(DeleteResult::new(permission_id))
     }
 }
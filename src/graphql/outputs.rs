@@ -1,13 +1,64 @@
+use crate::{
+    database::{
+        AccessToken, FolderQuota, Group, GroupMapping, Lock, Permission, Share, TrashedItem,
+    },
+    security::throttle::Lockout,
+};
 use async_graphql::{OutputType, SimpleObject};
+use std::marker::PhantomData;
 
+/// The result of removing something, identified by a `T`. `R` is the kind of thing that was
+/// removed, which only serves to give each result its own name.
+// Clippy reads the repeated `params` types as duplicated attributes
+#[allow(clippy::duplicated_attributes)]
 #[derive(SimpleObject)]
-#[graphql(concrete(name = "UserDeleteResult", params(String)))]
-#[graphql(concrete(name = "PermissionDeleteResult", params(i32)))]
-pub(crate) struct DeleteResult<T: OutputType> {
+#[graphql(concrete(name = "PermissionDeleteResult", params(i32, Permission)))]
+#[graphql(concrete(name = "AccessTokenDeleteResult", params(i32, AccessToken)))]
+#[graphql(concrete(name = "ShareDeleteResult", params(i32, Share)))]
+#[graphql(concrete(name = "TrashedItemDeleteResult", params(i32, TrashedItem)))]
+#[graphql(concrete(name = "GroupDeleteResult", params(String, Group)))]
+#[graphql(concrete(name = "LockDeleteResult", params(String, Lock)))]
+#[graphql(concrete(name = "LockoutDeleteResult", params(String, Lockout)))]
+#[graphql(concrete(name = "FolderQuotaDeleteResult", params(String, FolderQuota)))]
+#[graphql(concrete(name = "GroupMappingDeleteResult", params(String, GroupMapping)))]
+pub(crate) struct DeleteResult<T: OutputType, R: Send + Sync> {
     pub last_removed: T,
+    #[graphql(skip)]
+    removed: PhantomData<R>,
+}
+
+impl<T: OutputType, R: Send + Sync> DeleteResult<T, R> {
+    pub fn new(last_removed: T) -> DeleteResult<T, R> {
+        DeleteResult {
+            last_removed,
+            removed: PhantomData,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub(crate) struct UserDeleteResult {
+    pub last_removed: String,
+    /// The user that was given the deleted user's shares, file usage, trashed items and versions,
+    /// if any
+    pub reassigned_to: Option<String>,
+    pub removed_permissions: i32,
+    pub removed_access_tokens: i32,
+    pub removed_shares: i32,
+    pub removed_locks: i32,
+    pub removed_group_memberships: i32,
+    pub reassigned_shares: i32,
+    /// The number of stored files whose usage now counts against the new owner's quota
+    pub reassigned_usage: i32,
 }
 
 #[derive(SimpleObject)]
 pub(crate) struct RegenerateAccessTokenResult {
     pub token: String,
 }
+
+#[derive(SimpleObject)]
+pub(crate) struct CreateAccessTokenResult {
+    pub token: String,
+    pub access_token: AccessToken,
+}
@@ -1,9 +1,16 @@
 use super::fs::{self, Entry};
 use crate::{
     config::Config,
-    database::{Action, User},
+    database::{
+        Capability, FileVersion, FolderQuota, Group, GroupMapping, Lock, TrashedItem, User,
+    },
     error::Error,
-    security::{check_permissions, sanitize_path},
+    export::{self, Format},
+    security::{
+        check_permissions, explain_permissions, sanitize_path,
+        throttle::{Lockout, Throttle},
+        PermissionCache, PermissionExplanation, Visibility,
+    },
 };
 use async_graphql::{Context, Object, Result};
 use sqlx::PgPool;
@@ -14,7 +21,7 @@ pub struct Query;
 #[Object]
 impl Query {
     async fn me(&self, ctx: &Context<'_>) -> Result<User> {
-        ctx.data::<User>().map(Clone::clone)
+        ctx.data::<User>().cloned()
     }
 
     async fn users(&self, ctx: &Context<'_>) -> Result<Vec<User>> {
@@ -41,20 +48,155 @@ impl Query {
         Ok(user)
     }
 
+    async fn groups(&self, ctx: &Context<'_>) -> Result<Vec<Group>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let groups = Group::list(db).await?;
+
+        Ok(groups)
+    }
+
+    async fn group(&self, ctx: &Context<'_>, name: String) -> Result<Group> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let group = Group::get(db, &name).await?.ok_or(Error::NotFound)?;
+
+        Ok(group)
+    }
+
+    async fn explain_permission(
+        &self,
+        ctx: &Context<'_>,
+        user: String,
+        path: String,
+    ) -> Result<PermissionExplanation> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        let user = cache.user(db, &user).await?.ok_or(Error::NotFound)?;
+
+        let sanitized = sanitize_path(PathBuf::from(path))?;
+        let explanation = explain_permissions(db, cache, &user, &sanitized).await?;
+
+        Ok(explanation)
+    }
+
+    async fn export_configuration(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] format: Format,
+    ) -> Result<String> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let document = export::export(db).await?;
+
+        Ok(document.to_string(format)?)
+    }
+
+    async fn group_mappings(&self, ctx: &Context<'_>) -> Result<Vec<GroupMapping>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let mappings = GroupMapping::list(db).await?;
+
+        Ok(mappings)
+    }
+
+    async fn lockouts(&self, ctx: &Context<'_>) -> Result<Vec<Lockout>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let throttle = ctx.data::<Arc<Throttle>>()?;
+        Ok(throttle.list())
+    }
+
+    async fn folder_quotas(&self, ctx: &Context<'_>) -> Result<Vec<FolderQuota>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let quotas = FolderQuota::list(db).await?;
+
+        Ok(quotas)
+    }
+
+    async fn locks(&self, ctx: &Context<'_>) -> Result<Vec<Lock>> {
+        let current_user = ctx.data::<User>()?;
+        if !current_user.is_admin() {
+            return Err(Error::InvalidPermissions.into());
+        }
+
+        let db = ctx.data::<PgPool>()?;
+        let locks = Lock::list(db).await?;
+
+        Ok(locks)
+    }
+
+    async fn trash(&self, ctx: &Context<'_>) -> Result<Vec<TrashedItem>> {
+        let current_user = ctx.data::<User>()?;
+        let db = ctx.data::<PgPool>()?;
+
+        // Admins can see everything, while users only see what they deleted
+        let items = match current_user.is_admin() {
+            true => TrashedItem::list(db).await?,
+            false => TrashedItem::list_for(db, &current_user.username).await?,
+        };
+
+        Ok(items)
+    }
+
     async fn list_directory(&self, ctx: &Context<'_>, path: Option<String>) -> Result<Vec<Entry>> {
         let config = ctx.data::<Arc<Config>>()?;
         let db = ctx.data::<PgPool>()?;
         let user = ctx.data::<User>()?;
 
-        let sub_path = path.map(PathBuf::from).unwrap_or_else(PathBuf::new);
+        let sub_path = path.map(PathBuf::from).unwrap_or_default();
         let sanitized = sanitize_path(sub_path)?;
 
         // Check if the user has the necessary permissions
-        check_permissions(db, user, &sanitized, Action::Read).await?;
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+        check_permissions(db, cache, user, &sanitized, Capability::List).await?;
 
-        // Get the contents
-        let entries = fs::list(&config.path, sanitized).await?;
+        // Get the contents, hiding anything the user cannot see
+        let visibility = Visibility::load(db, cache, user).await?;
+        let entries = fs::list(&config.path, sanitized, &visibility).await?;
 
         Ok(entries)
     }
+
+    async fn versions(&self, ctx: &Context<'_>, path: String) -> Result<Vec<FileVersion>> {
+        let db = ctx.data::<PgPool>()?;
+        let user = ctx.data::<User>()?;
+
+        let cache = ctx.data::<Arc<PermissionCache>>()?;
+
+        let sanitized = sanitize_path(PathBuf::from(path))?;
+        check_permissions(db, cache, user, &sanitized, Capability::Read).await?;
+
+        let versions = FileVersion::list(db, &sanitized.to_string_lossy()).await?;
+        Ok(versions)
+    }
 }
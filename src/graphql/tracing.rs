@@ -37,10 +37,8 @@ impl Extension for TracingExtension {
         async move {
             let res = next.run(ctx, query, variables).await;
             if let Ok(doc) = &res {
-                Span::current().record(
-                    "source",
-                    &ctx.stringify_execute_doc(doc, variables).as_str(),
-                );
+                Span::current()
+                    .record("source", ctx.stringify_execute_doc(doc, variables).as_str());
             }
             res
         }
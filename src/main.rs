@@ -1,54 +1,121 @@
 use axum::{
     handler::Handler,
     middleware,
-    routing::{any, post},
+    routing::{any, get, post},
     Extension, Router, Server,
 };
+use clap::Parser;
 use eyre::WrapErr;
+use std::{io, net::SocketAddr, sync::Arc};
 use tokio::signal;
 use tracing::{info, warn};
 
+mod cli;
 mod config;
 mod database;
 mod error;
+mod export;
 mod frontend;
 mod graphql;
 mod logging;
 mod security;
+mod share;
+mod trash;
+mod versions;
 mod webdav;
 
-use security::{BasicAuth, SSOAuth};
+use cli::{Cli, Command};
+use config::Config;
+use security::{
+    credentials::CredentialCache, oidc, throttle::Throttle, BasicAuth, OIDCAuth, PermissionCache,
+    SSOAuth,
+};
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     color_eyre::install()?;
+    let cli = Cli::parse();
     if dotenv::dotenv().is_err() {
         warn!(".env file not found");
     }
-    tracing_subscriber::fmt::init();
 
     let config = config::load().wrap_err("failed to load config")?;
 
+    match cli.command {
+        None | Some(Command::Serve) => {
+            tracing_subscriber::fmt::init();
+            serve(config).await
+        }
+        Some(command) => {
+            // Keep stdout free for the command's output
+            tracing_subscriber::fmt().with_writer(io::stderr).init();
+            cli::run(command, config).await
+        }
+    }
+}
+
+/// Run the server until it is shutdown
+async fn serve(config: Arc<Config>) -> eyre::Result<()> {
     let db = database::connect(&config.database_url).await?;
+    tokio::spawn(trash::cleanup(db.clone(), config.clone()));
+    tokio::spawn(versions::cleanup(db.clone(), config.clone()));
+    tokio::spawn(security::cleanup_expired_permissions(db.clone()));
+    tokio::spawn(webdav::reconcile_folder_usage(db.clone(), config.clone()));
+
+    let throttle = Throttle::new(&config);
+    let credentials = CredentialCache::new();
+    let permissions = PermissionCache::new();
+
+    let oidc: oidc::OidcState = match &config.oidc {
+        Some(oidc_config) => {
+            let provider = oidc::Provider::discover(oidc_config.clone())
+                .await
+                .wrap_err("failed to discover OpenID Connect provider")?;
+            Some(Arc::new(provider))
+        }
+        None => None,
+    };
 
     // Configure routes
-    // The webdav and frontend routers are kept separate due to their separate authentication requirements
+    // The webdav, share, auth, and frontend routers are kept separate due to their separate authentication requirements
     let dav_router = Router::new()
         .route("/dav", any(webdav::handler))
         .route("/dav/*path", any(webdav::handler))
-        .layer(Extension(webdav::filesystem(&config.path)))
+        .layer(Extension(webdav::dav_handler(db.clone())))
         .layer(middleware::from_fn(security::ensure_authenticated))
         .layer(middleware::from_fn(security::extract::<_, BasicAuth>))
         .layer(middleware::from_fn(security::extract::<_, SSOAuth>));
+    let share_router = Router::new()
+        .route("/s/:slug", get(share::root))
+        .route("/s/:slug/*path", get(share::nested));
+    let auth_router = Router::new()
+        .route("/auth/login", get(oidc::login))
+        .route("/auth/callback", get(oidc::callback))
+        .route("/auth/logout", post(oidc::logout));
     let frontend_router = Router::new()
         .route("/api/graphql", post(graphql::handler))
         .fallback(frontend::fallback.into_service())
-        .layer(Extension(graphql::schema(config.clone(), db.clone())))
+        .layer(Extension(graphql::schema(
+            config.clone(),
+            db.clone(),
+            throttle.clone(),
+            credentials.clone(),
+            permissions.clone(),
+        )))
         .layer(middleware::from_fn(security::ensure_authenticated))
+        .layer(middleware::from_fn(oidc::redirect_to_login))
+        .layer(middleware::from_fn(security::extract::<_, OIDCAuth>))
         .layer(middleware::from_fn(security::extract::<_, SSOAuth>));
     let app = Router::new()
         .merge(dav_router)
+        .merge(share_router)
+        .merge(auth_router)
         .merge(frontend_router)
+        .layer(Extension(oidc))
+        .layer(Extension(throttle))
+        .layer(Extension(credentials))
+        .layer(Extension(permissions))
+        .layer(Extension(config.clone()))
         .layer(Extension(db))
         .layer(logging::layer());
 
@@ -78,7 +145,7 @@ async fn main() -> eyre::Result<()> {
     // Launch the server
     info!(address = %config.address, "listening and ready to handle requests");
     Server::bind(&config.address)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown())
         .await
         .wrap_err("failed to start server")?;
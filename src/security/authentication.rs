@@ -1,24 +1,38 @@
+use super::{
+    cache::PermissionCache,
+    credentials::CredentialCache,
+    oidc::OidcState,
+    throttle::{LockedOut, Throttle},
+};
 use crate::{
-    database::User,
+    config::Config,
+    database::{GroupMapping, User},
     error::{Error, Result},
 };
 use axum::{
+    extract::ConnectInfo,
     headers::{
         authorization::{Authorization, Basic},
         HeaderMapExt,
     },
-    http::Request,
+    http::{HeaderMap, Request},
     middleware::Next,
     response::Response,
 };
 use sqlx::PgPool;
-use tracing::info;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tracing::{info, warn};
 
 /// Check that there is an authenticated user
 pub async fn ensure_authenticated<B>(req: Request<B>, next: Next<B>) -> Result<Response> {
     if let Some(user) = req.extensions().get::<User>() {
         info!(user = %user.username, "authenticated");
         Ok(next.run(req).await)
+    } else if let Some(LockedOut(retry_after)) = req.extensions().get::<LockedOut>() {
+        Err(Error::TooManyRequests(*retry_after))
     } else {
         Err(Error::Unauthorized)
     }
@@ -26,64 +40,327 @@ pub async fn ensure_authenticated<B>(req: Request<B>, next: Next<B>) -> Result<R
 
 #[async_trait::async_trait]
 pub trait Extract {
-    async fn extract<B>(req: &Request<B>) -> Option<User>
+    /// Load the user from the request. Any additional information about how the user was
+    /// authenticated can be attached to the request's extensions.
+    async fn extract<B>(req: &mut Request<B>) -> Option<User>
     where
-        B: Sync;
+        B: Send;
 }
 
 /// Attempt to extract a user from the request.
 /// NOTE: this does not fail if the user could not be loaded
 pub async fn extract<B, E>(mut req: Request<B>, next: Next<B>) -> Result<Response>
 where
-    B: Sync,
+    B: Send,
     E: Extract,
 {
-    if let Some(user) = E::extract(&req).await {
+    if let Some(user) = E::extract(&mut req).await {
         req.extensions_mut().insert(user);
     }
 
     Ok(next.run(req).await)
 }
 
-/// Extract the user from SSO proxy headers (Remote-User & Remote-Name)
+/// Extract the user from SSO proxy headers (Remote-User, Remote-Name, Remote-Email &
+/// Remote-Groups). The headers are only trusted when they come from a trusted proxy.
 pub struct SSOAuth;
 
 #[async_trait::async_trait]
 impl Extract for SSOAuth {
-    async fn extract<B>(req: &Request<B>) -> Option<User>
+    async fn extract<B>(req: &mut Request<B>) -> Option<User>
     where
-        B: Sync,
+        B: Send,
     {
         let headers = req.headers();
         let db = req.extensions().get::<PgPool>().unwrap();
+        let config = req.extensions().get::<Arc<Config>>().unwrap();
+        let cache = req.extensions().get::<Arc<PermissionCache>>().unwrap();
 
         let username = headers.get("remote-user")?.to_str().ok()?;
         let display_name = headers.get("remote-name")?.to_str().ok()?;
+        let email = match headers.get("remote-email") {
+            Some(email) => Some(email.to_str().ok()?),
+            None => None,
+        };
+        let groups = match headers.get("remote-groups") {
+            Some(groups) => Some(groups.to_str().ok()?),
+            None => None,
+        };
+
+        let peer = peer_address(req);
+        if !from_trusted_proxy(config, peer, headers) {
+            warn!(?peer, %username, "rejected SSO headers from an untrusted source");
+            return None;
+        }
 
-        User::create_if_not_exists(db, username, display_name)
+        // The user only needs updating when the proxy sends different details
+        let login = format!("{display_name}\n{email:?}\n{groups:?}");
+        if let Some(user) = cache.login(username, &login) {
+            return Some(user);
+        }
+
+        let mut user = User::create_if_not_exists(db, username, display_name)
             .await
-            .ok()
+            .ok()?;
+        if user.email.as_deref() != email {
+            user.set_email(db, email).await.ok()?;
+        }
+
+        if let Some(groups) = groups {
+            let groups = groups
+                .split(',')
+                .map(str::trim)
+                .filter(|group| !group.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>();
+            GroupMapping::apply(db, &mut user, &groups).await.ok()?;
+        }
+
+        cache.remember_login(user.clone(), login);
+        Some(user)
     }
 }
 
-/// Extract the user from HTTP basic authentication
+/// Check if the request was sent by a trusted proxy, and that it knows the shared secret if one is
+/// configured
+fn from_trusted_proxy(config: &Config, peer: Option<IpAddr>, headers: &HeaderMap) -> bool {
+    let trusted_peer = match peer {
+        Some(ip) => config.trusted_proxies.iter().any(|net| net.contains(&ip)),
+        None => false,
+    };
+    let secret_valid = match &config.sso_secret {
+        Some(secret) => headers
+            .get("x-sso-secret")
+            .is_some_and(|value| constant_time_eq(value.as_bytes(), secret.as_bytes())),
+        None => true,
+    };
+
+    trusted_peer && secret_valid
+}
+
+/// Get the address of the connected peer
+fn peer_address<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical())
+}
+
+/// Get the address of the client. When connected through a trusted proxy, the address the proxy
+/// received the request from is used instead.
+pub fn client_address(config: &Config, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !config.trusted_proxies.iter().any(|net| net.contains(&peer)) {
+        return peer;
+    }
+
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .next_back();
+    forwarded.unwrap_or(peer)
+}
+
+/// Compare two byte strings without leaking where they differ through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Extract the user from a session started by logging in with the OpenID Connect provider
+pub struct OIDCAuth;
+
+#[async_trait::async_trait]
+impl Extract for OIDCAuth {
+    async fn extract<B>(req: &mut Request<B>) -> Option<User>
+    where
+        B: Send,
+    {
+        let db = req.extensions().get::<PgPool>().unwrap();
+        let cache = req.extensions().get::<Arc<PermissionCache>>().unwrap();
+        let provider = req.extensions().get::<OidcState>().unwrap().as_ref()?;
+
+        let username = provider.session(req.headers())?;
+        cache.user(db, &username).await.ok()?
+    }
+}
+
+/// Extract the user from HTTP basic authentication. The matching access token is attached to the
+/// request so its scope can be enforced. Successful verifications are cached briefly, as clients
+/// send the credentials with every request. Repeated failures lock out the username and client
+/// address, in which case the credentials are not checked at all.
 pub struct BasicAuth;
 
 #[async_trait::async_trait]
 impl Extract for BasicAuth {
-    async fn extract<B>(req: &Request<B>) -> Option<User>
+    async fn extract<B>(req: &mut Request<B>) -> Option<User>
     where
-        B: Sync,
+        B: Send,
     {
-        let headers = req.headers();
-        let db = req.extensions().get::<PgPool>().unwrap();
+        let extensions = req.extensions();
+        let db = extensions.get::<PgPool>().cloned().unwrap();
+        let config = extensions.get::<Arc<Config>>().cloned().unwrap();
+        let throttle = extensions.get::<Arc<Throttle>>().cloned().unwrap();
+        let credentials_cache = extensions.get::<Arc<CredentialCache>>().cloned().unwrap();
+        let permissions_cache = extensions.get::<Arc<PermissionCache>>().cloned().unwrap();
+
+        let credentials = req.headers().typed_get::<Authorization<Basic>>()?;
+        let username = credentials.username();
+        let address = client_address(&config, peer_address(req)?, req.headers());
+
+        if let Some(retry_after) = throttle.check(username, address) {
+            warn!(%username, %address, "rejected login attempt while locked out");
+            req.extensions_mut().insert(LockedOut(retry_after));
+            return None;
+        }
 
-        let credentials = headers.typed_get::<Authorization<Basic>>()?;
-        let user = User::get(db, credentials.username()).await.ok()??;
+        let password = credentials.password();
+        let user = permissions_cache.user(&db, username).await.ok()?;
+        let access_token = match (&user, credentials_cache.get(username, password)) {
+            // The token was marked as used when it was verified, less than a cache lifetime ago
+            (Some(_), Some(access_token)) => Some(access_token),
+            (Some(user), None) => {
+                let access_token = user.authenticate(&db, password).await.ok()?;
+                if let Some(access_token) = &access_token {
+                    credentials_cache.insert(username, password, access_token.clone());
+                }
+                access_token
+            }
+            (None, _) => None,
+        };
 
-        match user.access_token_valid(credentials.password()) {
-            true => Some(user),
-            false => None,
+        match (user, access_token) {
+            (Some(user), Some(access_token)) => {
+                throttle.succeeded(username, address);
+                req.extensions_mut().insert(access_token);
+                Some(user)
+            }
+            _ => {
+                warn!(%username, %address, "failed login attempt");
+                throttle.failed(username, address);
+                None
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{from_trusted_proxy, peer_address};
+    use crate::config::Config;
+    use axum::{
+        extract::ConnectInfo,
+        http::{HeaderMap, HeaderValue, Request},
+    };
+    use std::net::{IpAddr, SocketAddr};
+
+    fn ip(raw: &str) -> Option<IpAddr> {
+        Some(raw.parse().unwrap())
+    }
+
+    fn with_secret(secret: &str) -> Config {
+        Config {
+            sso_secret: Some(String::from(secret)),
+            ..Config::default()
+        }
+    }
+
+    fn secret_header(secret: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-sso-secret", HeaderValue::from_static(secret));
+        headers
+    }
+
+    #[test]
+    fn trusted_peer() {
+        let config = Config::default();
+        assert!(from_trusted_proxy(
+            &config,
+            ip("127.0.0.1"),
+            &HeaderMap::new()
+        ));
+        assert!(from_trusted_proxy(&config, ip("::1"), &HeaderMap::new()));
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let config = Config::default();
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("192.168.1.5"),
+            &HeaderMap::new()
+        ));
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("2001:db8::1"),
+            &HeaderMap::new()
+        ));
+    }
+
+    #[test]
+    fn missing_peer() {
+        let config = Config::default();
+        assert!(!from_trusted_proxy(&config, None, &HeaderMap::new()));
+    }
+
+    #[test]
+    fn valid_secret() {
+        let config = with_secret("hunter2");
+        assert!(from_trusted_proxy(
+            &config,
+            ip("127.0.0.1"),
+            &secret_header("hunter2")
+        ));
+    }
+
+    #[test]
+    fn bad_secret() {
+        let config = with_secret("hunter2");
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("127.0.0.1"),
+            &HeaderMap::new()
+        ));
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("127.0.0.1"),
+            &secret_header("hunter3")
+        ));
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("127.0.0.1"),
+            &secret_header("hunter")
+        ));
+    }
+
+    #[test]
+    fn secret_from_untrusted_peer() {
+        let config = with_secret("hunter2");
+        assert!(!from_trusted_proxy(
+            &config,
+            ip("192.168.1.5"),
+            &secret_header("hunter2")
+        ));
+    }
+
+    #[test]
+    fn missing_connect_info() {
+        let req = Request::new(());
+        assert_eq!(peer_address(&req), None);
+    }
+
+    #[test]
+    fn ipv4_mapped_peer() {
+        let mut req = Request::new(());
+        let addr = "[::ffff:127.0.0.1]:4000".parse::<SocketAddr>().unwrap();
+        req.extensions_mut().insert(ConnectInfo(addr));
+
+        let peer = peer_address(&req);
+        assert_eq!(peer, ip("127.0.0.1"));
+        assert!(from_trusted_proxy(
+            &Config::default(),
+            peer,
+            &HeaderMap::new()
+        ));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    database::{Permission, User},
+    error::Result,
+};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use time::{Duration, OffsetDateTime};
+
+/// How long users and permissions are cached for. Changes made through the API are applied
+/// immediately, this only bounds how long changes made elsewhere can go unnoticed.
+const CACHE_TTL: Duration = Duration::seconds(30);
+
+/// A cached value, along with when it was loaded
+struct Cached<T> {
+    value: T,
+    loaded_at: OffsetDateTime,
+}
+
+impl<T: Clone> Cached<T> {
+    fn new(value: T) -> Cached<T> {
+        Cached {
+            value,
+            loaded_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Get the value if it has not expired
+    fn fresh(&self) -> Option<T> {
+        (self.loaded_at + CACHE_TTL > OffsetDateTime::now_utc()).then(|| self.value.clone())
+    }
+}
+
+/// A user, along with the details from the SSO proxy they were last seen with
+#[derive(Clone)]
+struct CachedUser {
+    user: User,
+    login: Option<String>,
+}
+
+/// Caches users and the permissions that apply to them, so evaluating permissions for every
+/// request, and every entry of a directory listing, does not need to hit the database. Anything
+/// that changes a user or their permissions must invalidate the cache.
+#[derive(Default)]
+pub struct PermissionCache {
+    users: Mutex<HashMap<String, Cached<CachedUser>>>,
+    permissions: Mutex<HashMap<String, Cached<Arc<Vec<Permission>>>>>,
+}
+
+impl PermissionCache {
+    /// Create a new empty cache
+    pub fn new() -> Arc<PermissionCache> {
+        Arc::default()
+    }
+
+    /// Find a user by their username
+    pub async fn user(&self, db: &PgPool, username: &str) -> Result<Option<User>> {
+        if let Some(cached) = self.cached_user(username) {
+            return Ok(Some(cached.user));
+        }
+
+        let user = User::get(db, username).await?;
+        if let Some(user) = &user {
+            self.users.lock().unwrap().insert(
+                username.to_string(),
+                Cached::new(CachedUser {
+                    user: user.clone(),
+                    login: None,
+                }),
+            );
+        }
+
+        Ok(user)
+    }
+
+    /// Find a user that recently logged in through the SSO proxy with the same details
+    pub fn login(&self, username: &str, login: &str) -> Option<User> {
+        self.cached_user(username)
+            .filter(|cached| cached.login.as_deref() == Some(login))
+            .map(|cached| cached.user)
+    }
+
+    /// Remember the details a user logged in through the SSO proxy with. Logging in can change the
+    /// user's groups, so their permissions are reloaded.
+    pub fn remember_login(&self, user: User, login: String) {
+        self.permissions.lock().unwrap().remove(&user.username);
+        self.users.lock().unwrap().insert(
+            user.username.clone(),
+            Cached::new(CachedUser {
+                user,
+                login: Some(login),
+            }),
+        );
+    }
+
+    /// Get all the permissions that affect the user, including those inherited from their groups
+    pub async fn permissions(&self, db: &PgPool, user: &User) -> Result<Arc<Vec<Permission>>> {
+        let cached = self
+            .permissions
+            .lock()
+            .unwrap()
+            .get(&user.username)
+            .and_then(Cached::fresh);
+        if let Some(permissions) = cached {
+            return Ok(permissions);
+        }
+
+        let permissions = Arc::new(user.effective_permissions(db).await?);
+        self.permissions
+            .lock()
+            .unwrap()
+            .insert(user.username.clone(), Cached::new(permissions.clone()));
+
+        Ok(permissions)
+    }
+
+    /// Forget everything about a user, such as when they or their permissions change
+    pub fn invalidate(&self, username: &str) {
+        self.users.lock().unwrap().remove(username);
+        self.permissions.lock().unwrap().remove(username);
+    }
+
+    /// Forget everything, such as when a group or its permissions change
+    pub fn invalidate_all(&self) {
+        self.users.lock().unwrap().clear();
+        self.permissions.lock().unwrap().clear();
+    }
+
+    /// Get the cached user if it has not expired
+    fn cached_user(&self, username: &str) -> Option<CachedUser> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(username)
+            .and_then(Cached::fresh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cached, CachedUser, PermissionCache, CACHE_TTL};
+    use crate::database::{Action, Permission, User};
+    use sqlx::PgPool;
+    use std::sync::Arc;
+    use time::Duration;
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            name: username.to_string(),
+            default_access: Action::Read,
+            quota: None,
+            email: None,
+        }
+    }
+
+    fn permissions(username: &str) -> Arc<Vec<Permission>> {
+        Arc::new(vec![Permission {
+            id: 1,
+            applies_to: Some(username.to_string()),
+            applies_to_group: None,
+            path: String::from("shared"),
+            pattern: false,
+            action: Action::Modify,
+            custom_capabilities: None,
+            affects_children: true,
+            valid_from: None,
+            valid_until: None,
+        }])
+    }
+
+    /// A pool that is never connected to, so any cache miss fails
+    fn db() -> PgPool {
+        PgPool::connect_lazy("postgres://localhost/unused").unwrap()
+    }
+
+    /// Cache the user's permissions as if they were loaded the given time ago
+    fn cache_permissions(cache: &PermissionCache, username: &str, age: Duration) {
+        let mut cached = Cached::new(permissions(username));
+        cached.loaded_at -= age;
+        cache
+            .permissions
+            .lock()
+            .unwrap()
+            .insert(username.to_string(), cached);
+    }
+
+    fn has_permissions(cache: &PermissionCache, username: &str) -> bool {
+        let permissions = cache.permissions.lock().unwrap();
+        permissions.get(username).and_then(Cached::fresh).is_some()
+    }
+
+    #[tokio::test]
+    async fn cached_permissions() {
+        let cache = PermissionCache::new();
+        cache_permissions(&cache, "alice", Duration::ZERO);
+
+        let cached = cache.permissions(&db(), &user("alice")).await.unwrap();
+        assert_eq!(cached, permissions("alice"));
+    }
+
+    #[test]
+    fn expired_entries() {
+        let cache = PermissionCache::new();
+        cache.remember_login(user("alice"), String::from("Alice"));
+        cache_permissions(&cache, "alice", CACHE_TTL);
+        cache_permissions(&cache, "bob", CACHE_TTL - Duration::seconds(5));
+
+        cache
+            .users
+            .lock()
+            .unwrap()
+            .get_mut("alice")
+            .unwrap()
+            .loaded_at -= CACHE_TTL;
+
+        assert_eq!(cache.login("alice", "Alice"), None);
+        assert!(!has_permissions(&cache, "alice"));
+        assert!(has_permissions(&cache, "bob"));
+    }
+
+    #[test]
+    fn invalidate_clears_user_and_permissions() {
+        let cache = PermissionCache::new();
+        for username in ["alice", "bob"] {
+            cache.remember_login(user(username), String::from("login"));
+            cache_permissions(&cache, username, Duration::ZERO);
+        }
+
+        cache.invalidate("alice");
+        assert_eq!(cache.login("alice", "login"), None);
+        assert!(!has_permissions(&cache, "alice"));
+        assert_eq!(cache.login("bob", "login"), Some(user("bob")));
+        assert!(has_permissions(&cache, "bob"));
+
+        cache.invalidate_all();
+        assert_eq!(cache.login("bob", "login"), None);
+        assert!(!has_permissions(&cache, "bob"));
+    }
+
+    #[test]
+    fn login_requires_identical_details() {
+        let cache = PermissionCache::new();
+        cache.remember_login(user("alice"), String::from("Alice\nSome(\"admins\")"));
+
+        assert_eq!(
+            cache.login("alice", "Alice\nSome(\"admins\")"),
+            Some(user("alice"))
+        );
+        assert_eq!(cache.login("alice", "Alice\nSome(\"admins,users\")"), None);
+        assert_eq!(cache.login("alice", "Alice\nNone"), None);
+        assert_eq!(cache.login("bob", "Alice\nSome(\"admins\")"), None);
+    }
+
+    #[test]
+    fn login_needs_remembering() {
+        let cache = PermissionCache::new();
+        cache.users.lock().unwrap().insert(
+            String::from("alice"),
+            Cached::new(CachedUser {
+                user: user("alice"),
+                login: None,
+            }),
+        );
+
+        // Users loaded without logging in have no details to match
+        assert_eq!(cache.login("alice", ""), None);
+    }
+
+    #[test]
+    fn remember_login_drops_permissions() {
+        let cache = PermissionCache::new();
+        cache_permissions(&cache, "alice", Duration::ZERO);
+        cache_permissions(&cache, "bob", Duration::ZERO);
+
+        cache.remember_login(user("alice"), String::from("Alice"));
+        assert!(!has_permissions(&cache, "alice"));
+        assert!(has_permissions(&cache, "bob"));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::database::AccessToken;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use time::{Duration, OffsetDateTime};
+
+/// How long a successful verification is remembered for
+const CACHE_TTL: Duration = Duration::minutes(1);
+
+/// A verified access token, along with when it was verified
+struct Entry {
+    access_token: AccessToken,
+    verified_at: OffsetDateTime,
+}
+
+/// Remembers recently verified Basic credentials, so WebDAV clients sending them with every request
+/// do not need the token hash verified each time. Only a digest of the token is kept in memory.
+#[derive(Default)]
+pub struct CredentialCache {
+    entries: Mutex<HashMap<(String, [u8; 32]), Entry>>,
+}
+
+impl CredentialCache {
+    /// Create a new empty cache
+    pub fn new() -> Arc<CredentialCache> {
+        Arc::default()
+    }
+
+    /// Find the access token the credentials were recently verified against, if it has not
+    /// expired since
+    pub fn get(&self, username: &str, token: &str) -> Option<AccessToken> {
+        let now = OffsetDateTime::now_utc();
+        let entries = self.entries.lock().unwrap();
+
+        let entry = entries.get(&(username.to_string(), digest(token)))?;
+        let fresh = entry.verified_at + CACHE_TTL > now;
+
+        (fresh && !entry.access_token.is_expired()).then(|| entry.access_token.clone())
+    }
+
+    /// Remember that the credentials were verified against the access token
+    pub fn insert(&self, username: &str, token: &str, access_token: AccessToken) {
+        let now = OffsetDateTime::now_utc();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.verified_at + CACHE_TTL > now);
+
+        entries.insert(
+            (username.to_string(), digest(token)),
+            Entry {
+                access_token,
+                verified_at: now,
+            },
+        );
+    }
+
+    /// Forget all the verified credentials for a user, such as when their tokens change
+    pub fn invalidate(&self, username: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(owner, _), _| owner != username);
+    }
+}
+
+/// Hash the token so it is not kept in memory as-is
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CredentialCache;
+    use crate::database::AccessToken;
+    use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+    use rand_core::OsRng;
+    use time::{Duration, OffsetDateTime};
+
+    const TOKEN: &str = "5b0dbb1e-5f0c-4a43-9d0b-7c1f6a3e2d9a";
+
+    macro_rules! access_token {
+        (expires_at = $expires_at:expr) => {{
+            let salt = SaltString::generate(&mut OsRng);
+            let hash = Argon2::default()
+                .hash_password(TOKEN.as_bytes(), &salt)
+                .unwrap()
+                .to_string();
+
+            AccessToken {
+                id: 1,
+                owner: String::from("user"),
+                name: String::from("default"),
+                hash,
+                path: None,
+                action: None,
+                created_at: OffsetDateTime::now_utc(),
+                last_used_at: None,
+                expires_at: $expires_at,
+            }
+        }};
+    }
+
+    #[test]
+    fn cached_credentials() {
+        let cache = CredentialCache::new();
+        cache.insert("user", TOKEN, access_token!(expires_at = None));
+
+        assert!(cache.get("user", TOKEN).is_some());
+        assert!(cache.get("user", "wrong").is_none());
+        assert!(cache.get("other", TOKEN).is_none());
+    }
+
+    #[test]
+    fn invalidated_credentials() {
+        let cache = CredentialCache::new();
+        cache.insert("user", TOKEN, access_token!(expires_at = None));
+        cache.insert("other", TOKEN, access_token!(expires_at = None));
+
+        cache.invalidate("user");
+        assert!(cache.get("user", TOKEN).is_none());
+        assert!(cache.get("other", TOKEN).is_some());
+    }
+
+    #[test]
+    fn expired_access_token() {
+        let cache = CredentialCache::new();
+        let expires_at = OffsetDateTime::now_utc() - Duration::seconds(1);
+        cache.insert("user", TOKEN, access_token!(expires_at = Some(expires_at)));
+
+        assert!(cache.get("user", TOKEN).is_none());
+    }
+
+    #[test]
+    fn cache_hit_skips_verification() {
+        // The token was since regenerated, so only a cache hit can accept the old one
+        let salt = SaltString::generate(&mut OsRng);
+        let mut access_token = access_token!(expires_at = None);
+        access_token.hash = Argon2::default()
+            .hash_password(b"regenerated", &salt)
+            .unwrap()
+            .to_string();
+        assert!(!access_token.is_valid(TOKEN));
+
+        let cache = CredentialCache::new();
+        cache.insert("user", TOKEN, access_token.clone());
+
+        assert_eq!(cache.get("user", TOKEN), Some(access_token));
+    }
+}
@@ -1,13 +1,32 @@
-use crate::error::{Error, Result};
-use std::path::{Component, PathBuf};
+use crate::{
+    database::{Action, Capabilities, Capability, NewPermission},
+    error::{Error, Result},
+    trash::TRASH_DIR,
+    versions::VERSIONS_DIR,
+};
+use std::path::{Component, Path, PathBuf};
+use time::OffsetDateTime;
 
 mod authentication;
+mod cache;
+pub mod credentials;
+pub mod oidc;
+mod pattern;
 mod permissions;
+pub mod throttle;
 
-pub use authentication::{ensure_authenticated, extract, BasicAuth, SSOAuth};
-pub use permissions::check_permissions;
+pub use authentication::{
+    client_address, ensure_authenticated, extract, BasicAuth, OIDCAuth, SSOAuth,
+};
+pub use cache::PermissionCache;
+pub use pattern::validate as validate_pattern;
+pub use permissions::{
+    check_permissions, check_token_scope, cleanup_expired_permissions, explain_permissions,
+    PermissionExplanation, Visibility,
+};
 
-/// Sanitize a path, ensuring it does not escape the base directory
+/// Sanitize a path, ensuring it does not escape the base directory or reach into the directories
+/// used for internal storage
 pub fn sanitize_path(raw: PathBuf) -> Result<PathBuf> {
     let mut sanitized = Vec::new();
 
@@ -26,5 +45,125 @@ pub fn sanitize_path(raw: PathBuf) -> Result<PathBuf> {
     }
 
     let path = PathBuf::from_iter(sanitized);
+    if is_reserved(&path) {
+        return Err(Error::NotFound);
+    }
+
     Ok(path)
 }
+
+/// Check if the path is within one of the directories used for internal storage, which must never
+/// be exposed directly
+pub fn is_reserved(path: &Path) -> bool {
+    match path.components().next() {
+        Some(Component::Normal(first)) => first == TRASH_DIR || first == VERSIONS_DIR,
+        _ => false,
+    }
+}
+
+/// Remove any leading and trailing slashes
+pub fn normalize_path(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter(|&c| c != Component::RootDir)
+        .collect::<PathBuf>()
+        .display()
+        .to_string()
+}
+
+/// Validate and normalize the details of a permission before assigning it
+pub fn new_permission(
+    path: &str,
+    pattern: bool,
+    action: Action,
+    capabilities: Option<Vec<Capability>>,
+    affects_children: bool,
+    valid_from: Option<OffsetDateTime>,
+    valid_until: Option<OffsetDateTime>,
+) -> std::result::Result<NewPermission, &'static str> {
+    let path = normalize_path(path);
+    if pattern {
+        validate_pattern(&path)?;
+    }
+
+    if matches!(valid_until, Some(until) if until <= OffsetDateTime::now_utc()) {
+        return Err("validity must end in the future");
+    } else if matches!((valid_from, valid_until), (Some(from), Some(until)) if from >= until) {
+        return Err("validity must start before it ends");
+    }
+
+    Ok(NewPermission {
+        path,
+        pattern,
+        action,
+        custom_capabilities: capabilities.map(Capabilities::from_iter),
+        affects_children,
+        valid_from,
+        valid_until,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_permission, normalize_path};
+    use crate::database::{Action, Capabilities, Capability};
+    use time::{Duration, OffsetDateTime};
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize_path("/docs/reports/"), "docs/reports");
+        assert_eq!(normalize_path("docs"), "docs");
+        assert_eq!(normalize_path("/"), "");
+    }
+
+    #[test]
+    fn valid_permission() {
+        let permission = new_permission(
+            "/inbox/",
+            false,
+            Action::Deny,
+            Some(vec![Capability::Create]),
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(permission.path, "inbox");
+        assert_eq!(
+            permission.custom_capabilities,
+            Some(Capabilities::from_iter([Capability::Create]))
+        );
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let result = new_permission("**.key", true, Action::Deny, None, false, None, None);
+        assert!(result.is_err());
+
+        // Only patterns are checked
+        let result = new_permission("**.key", false, Action::Deny, None, false, None, None);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn invalid_validity() {
+        let now = OffsetDateTime::now_utc();
+        let permission = |from: Option<OffsetDateTime>, until: Option<OffsetDateTime>| {
+            new_permission("docs", false, Action::Read, None, true, from, until)
+        };
+
+        assert!(permission(None, Some(now - Duration::minutes(1))).is_err());
+        assert!(permission(
+            Some(now + Duration::hours(2)),
+            Some(now + Duration::hours(1))
+        )
+        .is_err());
+        assert!(permission(
+            Some(now - Duration::hours(1)),
+            Some(now + Duration::hours(1))
+        )
+        .is_ok());
+        assert!(permission(Some(now + Duration::hours(1)), None).is_ok());
+    }
+}
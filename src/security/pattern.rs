@@ -0,0 +1,93 @@
+use std::path::{Component, Path};
+
+/// Check a glob pattern is well-formed, returning why it is not.
+///
+/// Patterns are matched against paths one segment at a time. Within a segment, `*` matches any
+/// number of characters and `?` matches a single character, while a `**` segment matches any
+/// number of segments, including none.
+pub fn validate(pattern: &str) -> Result<(), &'static str> {
+    if pattern.is_empty() {
+        return Err("pattern cannot be empty");
+    }
+
+    for segment in pattern.split('/') {
+        match segment {
+            "" => return Err("pattern cannot contain empty segments"),
+            "." | ".." => return Err("pattern cannot contain relative segments"),
+            "**" => {}
+            _ if segment.contains("**") => {
+                return Err("`**` must be the only thing in its segment")
+            }
+            _ if segment.contains(['[', ']', '{', '}', '\\']) => {
+                return Err("only `*`, `**` and `?` are supported in patterns")
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if the path matches the pattern. When `children` is set, paths within a matching
+/// directory also match.
+pub fn matches(pattern: &str, path: &Path, children: bool) -> bool {
+    let segments = pattern.split('/').collect::<Vec<_>>();
+    let components = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(segment) => Some(segment.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let components = components.iter().map(|c| c.as_ref()).collect::<Vec<_>>();
+
+    matches_segments(&segments, &components, children)
+}
+
+/// Match the pattern's segments against the path's components
+fn matches_segments(segments: &[&str], components: &[&str], children: bool) -> bool {
+    match segments.split_first() {
+        None => components.is_empty() || children,
+        Some((&"**", rest)) => {
+            (0..=components.len()).any(|skip| matches_segments(rest, &components[skip..], children))
+        }
+        Some((segment, rest)) => match components.split_first() {
+            Some((component, remaining)) => {
+                matches_segment(segment, component) && matches_segments(rest, remaining, children)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a single segment of the pattern against a path component
+fn matches_segment(segment: &str, component: &str) -> bool {
+    let segment = segment.chars().collect::<Vec<_>>();
+    let component = component.chars().collect::<Vec<_>>();
+
+    // Greedy wildcard matching, backtracking to the most recent `*` on a mismatch
+    let (mut s, mut c) = (0, 0);
+    let mut backtrack = None;
+    while c < component.len() {
+        match segment.get(s) {
+            Some('*') => {
+                backtrack = Some((s, c));
+                s += 1;
+            }
+            Some(&expected) if expected == '?' || expected == component[c] => {
+                s += 1;
+                c += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    s = star + 1;
+                    c = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    segment[s..].iter().all(|&ch| ch == '*')
+}
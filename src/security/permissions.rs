@@ -1,23 +1,37 @@
+use super::{cache::PermissionCache, is_reserved, pattern};
 use crate::{
-    database::{Action, Permission, User},
+    database::{AccessToken, Action, Capabilities, Capability, Permission, User},
     error::{Error, Result},
 };
+use async_graphql::SimpleObject;
 use sqlx::PgPool;
-use std::path::Path;
-use tracing::warn;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    path::{Component, Path},
+    sync::Arc,
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio::time::interval;
+use tracing::{error, info, instrument, warn};
+
+/// How often to check for permissions that have stopped applying
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
-/// Check the user's permissions are sufficient for the requested action and path
+/// Check the user's permissions grant the required capability for the path
 pub async fn check_permissions(
     db: &PgPool,
+    cache: &PermissionCache,
     user: &User,
     path: &Path,
-    required: Action,
+    required: Capability,
 ) -> Result<()> {
     if user.default_access != Action::Admin {
-        let permissions = user.permissions(db).await?;
-        let effective = effective_permission(permissions, user.default_access, path);
+        let permissions = cache.permissions(db, user).await?;
+        let effective = effective_permission(&permissions, user.default_access, path);
 
-        if effective < required {
+        if !effective.contains(required) {
             warn!(?effective, ?required, resource = %path.display(), "invalid permissions for resource");
             return Err(Error::InvalidPermissions);
         }
@@ -26,34 +40,326 @@ pub async fn check_permissions(
     Ok(())
 }
 
-/// Find the effective permission for the given path by finding the most specific permission
-/// applied to the user.
-fn effective_permission(permissions: Vec<Permission>, default: Action, path: &Path) -> Action {
-    let mut effective = default;
+/// Determines which paths a user is allowed to see in directory listings. The user's permissions
+/// are only loaded once so it can be cheaply applied to every entry in a listing.
+#[derive(Clone, Debug)]
+pub struct Visibility {
+    permissions: Arc<Vec<Permission>>,
+    default: Action,
+}
+
+impl Visibility {
+    /// Load the visibility rules for the user
+    pub async fn load(db: &PgPool, cache: &PermissionCache, user: &User) -> Result<Visibility> {
+        let permissions = match user.is_admin() {
+            true => Arc::default(),
+            false => cache.permissions(db, user).await?,
+        };
+
+        Ok(Visibility {
+            permissions,
+            default: user.default_access,
+        })
+    }
+
+    /// Check if the path should be shown to the user
+    pub fn is_visible(&self, path: &Path) -> bool {
+        !is_reserved(path)
+            && effective_permission(&self.permissions, self.default, path)
+                .contains(Capability::List)
+    }
+}
+
+/// Check the scope of the access token the user authenticated with permits the required
+/// capability for the path
+pub fn check_token_scope(
+    access_token: &AccessToken,
+    path: &Path,
+    required: Capability,
+) -> Result<()> {
+    if !access_token.allows(path, required) {
+        warn!(token = %access_token.name, ?required, resource = %path.display(), "access token scope does not permit resource");
+        return Err(Error::InvalidPermissions);
+    }
+
+    Ok(())
+}
+
+/// Periodically remove permissions that have stopped applying. They are already ignored when
+/// evaluating permissions, this only keeps them from piling up.
+#[instrument(skip_all)]
+pub async fn cleanup_expired_permissions(db: PgPool) {
+    let mut interval = interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match Permission::delete_expired(&db).await {
+            Ok(0) => {}
+            Ok(count) => info!(count, "removed expired permissions"),
+            Err(e) => error!(error = %e, "failed to remove expired permissions"),
+        }
+    }
+}
+
+/// Why a user has the access they do to a path
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PermissionExplanation {
+    /// The capabilities the user has for the path
+    pub effective: Vec<Capability>,
+    /// The user's default access, used when no permission matches
+    pub default: Action,
+    /// The permissions that apply to the path, from most to least specific
+    pub matches: Vec<PermissionMatch>,
+}
+
+/// A permission that applies to the path being explained
+#[derive(Clone, Debug, SimpleObject)]
+pub struct PermissionMatch {
+    pub permission: Permission,
+    /// The user the permission is assigned to directly
+    pub user: Option<String>,
+    /// The group the permission is inherited from
+    pub group: Option<String>,
+    /// Whether this permission contributed to the effective capabilities
+    pub won: bool,
+}
+
+/// Explain how the user's permissions are evaluated for the path, using the same rules as
+/// [`check_permissions`]
+pub async fn explain_permissions(
+    db: &PgPool,
+    cache: &PermissionCache,
+    user: &User,
+    path: &Path,
+) -> Result<PermissionExplanation> {
+    if user.default_access == Action::Admin {
+        return Ok(PermissionExplanation {
+            effective: Capabilities::ALL.iter().collect(),
+            default: Action::Admin,
+            matches: Vec::new(),
+        });
+    }
+
+    let permissions = cache.permissions(db, user).await?;
+    let evaluation = evaluate(&permissions, user.default_access, path);
+
+    let matches = evaluation
+        .matches
+        .into_iter()
+        .map(|permission| PermissionMatch {
+            won: evaluation
+                .decided_by
+                .iter()
+                .any(|&winner| std::ptr::eq(winner, permission)),
+            user: permission.applies_to.clone(),
+            group: permission.applies_to_group.clone(),
+            permission: permission.clone(),
+        })
+        .collect();
+
+    Ok(PermissionExplanation {
+        effective: evaluation.capabilities.iter().collect(),
+        default: user.default_access,
+        matches,
+    })
+}
+
+/// Find the effective capabilities for the given path by finding the most specific permission
+/// applied to the user. Permissions outside of their validity window are ignored.
+///
+/// A permission is more specific than another when:
+///   1. it names the path exactly, rather than matching it with a pattern, rather than applying to
+///      it through one of its parents
+///   2. otherwise, its path has more segments, not counting `**`
+///   3. otherwise, it grants fewer capabilities
+///
+/// Permissions inherited from groups are merged using the following precedence:
+///   1. a matching permission assigned directly to the user always wins
+///   2. otherwise, each group is evaluated on its own and the capabilities from all of them are
+///      combined
+///   3. otherwise, the capabilities of the user's default access apply
+fn effective_permission(permissions: &[Permission], default: Action, path: &Path) -> Capabilities {
+    evaluate(permissions, default, path).capabilities
+}
+
+/// How a permission applies to a path, from least to most specific
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum MatchKind {
+    /// The permission applies to one of the path's parents and affects its children
+    Inherited,
+    /// The permission's pattern matches the path
+    Pattern,
+    /// The permission's path is the path
+    Exact,
+}
+
+/// The outcome of evaluating a user's permissions for a path
+struct Evaluation<'p> {
+    capabilities: Capabilities,
+    /// The permissions that apply to the path, from most to least specific
+    matches: Vec<&'p Permission>,
+    /// The permissions the capabilities came from, empty if from the default
+    decided_by: Vec<&'p Permission>,
+}
+
+/// Evaluate the permissions for the path, keeping track of which ones applied. See
+/// [`effective_permission`] for the precedence rules.
+fn evaluate<'p>(permissions: &'p [Permission], default: Action, path: &Path) -> Evaluation<'p> {
+    let now = OffsetDateTime::now_utc();
+    let mut matches = permissions
+        .iter()
+        .filter(|permission| permission.is_active(now))
+        .filter_map(|permission| {
+            let kind = match_kind(permission, path)?;
+            Some((specificity(permission, kind), permission))
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|&(specificity, _)| Reverse(specificity));
 
-    for permission in permissions {
-        if (path.starts_with(&permission.path) && permission.affects_children)
-            || path == Path::new(&permission.path)
-        {
-            effective = permission.action;
+    // Matches are sorted, so the first for each target is the most specific
+    let mut from_user = None;
+    let mut from_groups = HashMap::new();
+    for &(_, permission) in &matches {
+        match &permission.applies_to_group {
+            Some(group) => {
+                from_groups.entry(group.as_str()).or_insert(permission);
+            }
+            None => {
+                from_user.get_or_insert(permission);
+            }
         }
     }
 
-    effective
+    let decided_by = match from_user {
+        Some(permission) => vec![permission],
+        None => from_groups.into_values().collect::<Vec<_>>(),
+    };
+    let capabilities = match decided_by.is_empty() {
+        true => default.capabilities(),
+        false => decided_by
+            .iter()
+            .fold(Capabilities::NONE, |set, permission| {
+                set.union(permission.capabilities())
+            }),
+    };
+
+    Evaluation {
+        capabilities,
+        matches: matches
+            .into_iter()
+            .map(|(_, permission)| permission)
+            .collect(),
+        decided_by,
+    }
+}
+
+/// Order permissions by how specifically they apply to a path. Ties are broken by the fewest
+/// capabilities, then by the oldest permission, so the outcome never depends on the order
+/// permissions are loaded in.
+fn specificity(
+    permission: &Permission,
+    kind: MatchKind,
+) -> (MatchKind, usize, Reverse<usize>, Reverse<i32>) {
+    let depth = match permission.pattern {
+        true => permission.path.split('/').filter(|&s| s != "**").count(),
+        false => Path::new(&permission.path)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count(),
+    };
+
+    (
+        kind,
+        depth,
+        Reverse(permission.capabilities().len()),
+        Reverse(permission.id),
+    )
+}
+
+/// Check how the permission applies to the path, either directly, through one of its parents, or
+/// by matching its pattern
+fn match_kind(permission: &Permission, path: &Path) -> Option<MatchKind> {
+    if permission.pattern {
+        if pattern::matches(&permission.path, path, false) {
+            Some(MatchKind::Pattern)
+        } else if permission.affects_children && pattern::matches(&permission.path, path, true) {
+            Some(MatchKind::Inherited)
+        } else {
+            None
+        }
+    } else if path == Path::new(&permission.path) {
+        Some(MatchKind::Exact)
+    } else if permission.affects_children && path.starts_with(&permission.path) {
+        Some(MatchKind::Inherited)
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::database::Action;
+    use crate::database::{Action, Capabilities, Capability};
 
     macro_rules! permission {
         (path = $path:expr, action = $action:expr, children = $children:expr $(,)?) => {
             crate::database::Permission {
                 id: 1,
-                applies_to: String::from("user"),
+                applies_to: Some(String::from("user")),
+                applies_to_group: None,
+                path: $path.into(),
+                pattern: false,
+                action: $action,
+                custom_capabilities: None,
+                affects_children: $children,
+                valid_from: None,
+                valid_until: None,
+            }
+        };
+        (group = $group:expr, path = $path:expr, action = $action:expr, children = $children:expr $(,)?) => {
+            crate::database::Permission {
+                id: 1,
+                applies_to: None,
+                applies_to_group: Some(String::from($group)),
                 path: $path.into(),
+                pattern: false,
+                action: $action,
+                custom_capabilities: None,
+                affects_children: $children,
+                valid_from: None,
+                valid_until: None,
+            }
+        };
+        (path = $path:expr, capabilities = [$($capability:expr),* $(,)?], children = $children:expr $(,)?) => {
+            crate::database::Permission {
+                custom_capabilities: Some(crate::database::Capabilities::from_iter([$($capability),*])),
+                ..permission!(path = $path, action = Action::Deny, children = $children)
+            }
+        };
+        (group = $group:expr, path = $path:expr, capabilities = [$($capability:expr),* $(,)?], children = $children:expr $(,)?) => {
+            crate::database::Permission {
+                custom_capabilities: Some(crate::database::Capabilities::from_iter([$($capability),*])),
+                ..permission!(group = $group, path = $path, action = Action::Deny, children = $children)
+            }
+        };
+        (pattern = $pattern:expr, capabilities = [$($capability:expr),* $(,)?], children = $children:expr $(,)?) => {
+            crate::database::Permission {
+                custom_capabilities: Some(crate::database::Capabilities::from_iter([$($capability),*])),
+                ..permission!(pattern = $pattern, action = Action::Deny, children = $children)
+            }
+        };
+        (pattern = $pattern:expr, action = $action:expr, children = $children:expr $(,)?) => {
+            crate::database::Permission {
+                id: 1,
+                applies_to: Some(String::from("user")),
+                applies_to_group: None,
+                path: $pattern.into(),
+                pattern: true,
                 action: $action,
+                custom_capabilities: None,
                 affects_children: $children,
+                valid_from: None,
+                valid_until: None,
             }
         };
     }
@@ -85,7 +391,7 @@ mod tests {
         (default = $default:expr, path = $path:expr, permissions = $permissions:expr) => {
             {
                 use std::path::Path;
-                super::effective_permission($permissions, $default, Path::new($path))
+                super::effective_permission(&$permissions, $default, Path::new($path))
             }
         };
     }
@@ -93,7 +399,7 @@ mod tests {
     #[test]
     fn default_action() {
         let action = evaluate_permissions!(default = Action::Modify);
-        assert_eq!(action, Action::Modify);
+        assert_eq!(action, Action::Modify.capabilities());
     }
 
     #[test]
@@ -104,7 +410,7 @@ mod tests {
                 { path = "/test", action = Action::Read, children = false },
             ]
         );
-        assert_eq!(action, Action::Read);
+        assert_eq!(action, Action::Read.capabilities());
     }
 
     #[test]
@@ -115,7 +421,7 @@ mod tests {
                 { path = "/abc", action = Action::Read, children = true },
             ]
         );
-        assert_eq!(action, Action::Read);
+        assert_eq!(action, Action::Read.capabilities());
     }
 
     #[test]
@@ -127,7 +433,7 @@ mod tests {
                 { path = "/abc/def", action = Action::Modify, children = false },
             ]
         );
-        assert_eq!(action, Action::Modify);
+        assert_eq!(action, Action::Modify.capabilities());
     }
 
     #[test]
@@ -152,7 +458,7 @@ mod tests {
                 path = "/folder/abcdef",
                 permissions = permissions.clone()
             ),
-            Action::Read
+            Action::Read.capabilities()
         );
         assert_eq!(
             evaluate_permissions!(
@@ -160,7 +466,7 @@ mod tests {
                 path = "/folder/sub/no",
                 permissions = permissions.clone()
             ),
-            Action::Deny
+            Action::Deny.capabilities()
         );
         assert_eq!(
             evaluate_permissions!(
@@ -168,7 +474,7 @@ mod tests {
                 path = "/folder/sub/file",
                 permissions = permissions.clone()
             ),
-            Action::Modify
+            Action::Modify.capabilities()
         );
         assert_eq!(
             evaluate_permissions!(
@@ -176,7 +482,623 @@ mod tests {
                 path = "/folder/sub",
                 permissions = permissions
             ),
-            Action::Read
+            Action::Read.capabilities()
+        );
+    }
+
+    #[test]
+    fn group_permission() {
+        let permissions = vec![permission!(
+            group = "team",
+            path = "/project",
+            action = Action::Read,
+            children = true,
+        )];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Deny,
+                path = "/project/file",
+                permissions = permissions
+            ),
+            Action::Read.capabilities()
+        );
+    }
+
+    #[test]
+    fn user_permission_overrides_group() {
+        let permissions = vec![
+            permission!(path = "/project", action = Action::Deny, children = true),
+            permission!(
+                group = "team",
+                path = "/project/file",
+                action = Action::Modify,
+                children = false,
+            ),
+        ];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Modify,
+                path = "/project/file",
+                permissions = permissions
+            ),
+            Action::Deny.capabilities()
+        );
+    }
+
+    #[test]
+    fn most_permissive_group_wins() {
+        let permissions = vec![
+            permission!(
+                group = "writers",
+                path = "/project",
+                action = Action::Modify,
+                children = true,
+            ),
+            permission!(
+                group = "readers",
+                path = "/project",
+                action = Action::Read,
+                children = true,
+            ),
+            permission!(
+                group = "readers",
+                path = "/project/secret",
+                action = Action::Deny,
+                children = false,
+            ),
+        ];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Deny,
+                path = "/project/file",
+                permissions = permissions.clone()
+            ),
+            Action::Modify.capabilities()
+        );
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Deny,
+                path = "/project/secret",
+                permissions = permissions
+            ),
+            Action::Modify.capabilities()
+        );
+    }
+
+    #[test]
+    fn pattern_matching_extension() {
+        let permissions = vec![permission!(
+            pattern = "**/*.key",
+            action = Action::Deny,
+            children = false,
+        )];
+
+        for (path, expected) in [
+            ("/server.key", Action::Deny),
+            ("/certs/nested/server.key", Action::Deny),
+            ("/certs/server.key.bak", Action::Modify),
+            ("/certs/key", Action::Modify),
+            ("/certs", Action::Modify),
+        ] {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Modify,
+                    path = path,
+                    permissions = permissions.clone()
+                ),
+                expected.capabilities(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn pattern_matching_segment() {
+        let permissions = vec![
+            permission!(path = "/projects", action = Action::Read, children = true),
+            permission!(
+                pattern = "projects/*/private",
+                action = Action::Deny,
+                children = true,
+            ),
+        ];
+
+        for (path, expected) in [
+            ("/projects/alpha/private", Action::Deny),
+            ("/projects/alpha/private/notes.txt", Action::Deny),
+            ("/projects/alpha/public", Action::Read),
+            ("/projects/alpha/nested/private", Action::Read),
+            ("/projects/private", Action::Read),
+        ] {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Modify,
+                    path = path,
+                    permissions = permissions.clone()
+                ),
+                expected.capabilities(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn pattern_without_children() {
+        let permissions = vec![permission!(
+            pattern = "projects/*/private",
+            action = Action::Deny,
+            children = false,
+        )];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Modify,
+                path = "/projects/alpha/private",
+                permissions = permissions.clone()
+            ),
+            Action::Deny.capabilities()
+        );
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Modify,
+                path = "/projects/alpha/private/notes.txt",
+                permissions = permissions
+            ),
+            Action::Modify.capabilities()
         );
     }
+
+    #[test]
+    fn pattern_single_character() {
+        let permissions = vec![permission!(
+            pattern = "reports/20??-*.pdf",
+            action = Action::Read,
+            children = false,
+        )];
+
+        for (path, expected) in [
+            ("/reports/2022-q3.pdf", Action::Read),
+            ("/reports/2022-.pdf", Action::Read),
+            ("/reports/202-q3.pdf", Action::Deny),
+            ("/reports/2022-q3.pdf.txt", Action::Deny),
+        ] {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = path,
+                    permissions = permissions.clone()
+                ),
+                expected.capabilities(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn pattern_validation() {
+        use crate::security::validate_pattern;
+
+        assert!(validate_pattern("**/*.key").is_ok());
+        assert!(validate_pattern("projects/*/private").is_ok());
+        assert!(validate_pattern("reports/20??-*.pdf").is_ok());
+
+        assert!(validate_pattern("").is_err());
+        assert!(validate_pattern("projects//private").is_err());
+        assert!(validate_pattern("projects/../private").is_err());
+        assert!(validate_pattern("projects/**.key").is_err());
+        assert!(validate_pattern("projects/[abc]").is_err());
+        assert!(validate_pattern("projects/{a,b}").is_err());
+    }
+
+    #[test]
+    fn evaluation_tracks_winner() {
+        use std::path::Path;
+
+        let permissions = vec![
+            permission!(
+                group = "readers",
+                path = "/project",
+                action = Action::Read,
+                children = true,
+            ),
+            permission!(
+                group = "writers",
+                path = "/project",
+                action = Action::Modify,
+                children = true,
+            ),
+            permission!(path = "/other", action = Action::Deny, children = true),
+        ];
+
+        let evaluation = super::evaluate(&permissions, Action::Deny, Path::new("/project/file"));
+        assert_eq!(evaluation.capabilities, Action::Modify.capabilities());
+        assert_eq!(evaluation.matches, vec![&permissions[0], &permissions[1]]);
+        assert_eq!(evaluation.decided_by.len(), 2);
+
+        let evaluation = super::evaluate(&permissions, Action::Read, Path::new("/elsewhere"));
+        assert_eq!(evaluation.capabilities, Action::Read.capabilities());
+        assert!(evaluation.matches.is_empty());
+        assert!(evaluation.decided_by.is_empty());
+    }
+
+    #[test]
+    fn order_does_not_matter() {
+        let mut permissions = vec![
+            permission!(pattern = "abc/*", action = Action::Modify, children = false),
+            permission!(path = "/abc", action = Action::Read, children = true),
+            permission!(path = "/abc/def", action = Action::Deny, children = true),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/abc/def",
+                    permissions = permissions.clone()
+                ),
+                Action::Deny.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/abc/def/ghi",
+                    permissions = permissions.clone()
+                ),
+                Action::Deny.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/abc/xyz",
+                    permissions = permissions.clone()
+                ),
+                Action::Modify.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/abc/xyz/ghi",
+                    permissions = permissions.clone()
+                ),
+                Action::Read.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn deepest_parent_wins() {
+        let mut permissions = vec![
+            permission!(path = "/a/b", action = Action::Modify, children = true),
+            permission!(path = "/a", action = Action::Deny, children = true),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Read,
+                    path = "/a/b/c/d",
+                    permissions = permissions.clone()
+                ),
+                Action::Modify.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn exact_path_beats_pattern() {
+        let mut permissions = vec![
+            permission!(
+                path = "/certs/ca.key",
+                action = Action::Read,
+                children = false
+            ),
+            permission!(
+                pattern = "**/*.key",
+                action = Action::Deny,
+                children = false
+            ),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Modify,
+                    path = "/certs/ca.key",
+                    permissions = permissions.clone()
+                ),
+                Action::Read.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Modify,
+                    path = "/certs/server.key",
+                    permissions = permissions.clone()
+                ),
+                Action::Deny.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn pattern_beats_inherited() {
+        let mut permissions = vec![
+            permission!(
+                pattern = "**/*.key",
+                action = Action::Deny,
+                children = false
+            ),
+            permission!(
+                path = "/certs/nested",
+                action = Action::Modify,
+                children = true
+            ),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Read,
+                    path = "/certs/nested/server.key",
+                    permissions = permissions.clone()
+                ),
+                Action::Deny.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn equal_specificity_most_restrictive_wins() {
+        let mut permissions = vec![
+            permission!(
+                pattern = "shared/*.txt",
+                action = Action::Modify,
+                children = false
+            ),
+            permission!(
+                pattern = "shared/notes.*",
+                action = Action::Read,
+                children = false
+            ),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/shared/notes.txt",
+                    permissions = permissions.clone()
+                ),
+                Action::Read.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/shared/todo.txt",
+                    permissions = permissions.clone()
+                ),
+                Action::Modify.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn most_specific_within_group() {
+        let mut permissions = vec![
+            permission!(
+                group = "team",
+                path = "/project/archive",
+                action = Action::Read,
+                children = true,
+            ),
+            permission!(
+                group = "team",
+                path = "/project",
+                action = Action::Modify,
+                children = true,
+            ),
+        ];
+
+        for _ in 0..2 {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/project/archive/2021",
+                    permissions = permissions.clone()
+                ),
+                Action::Read.capabilities()
+            );
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Deny,
+                    path = "/project/current",
+                    permissions = permissions.clone()
+                ),
+                Action::Modify.capabilities()
+            );
+            permissions.reverse();
+        }
+    }
+
+    #[test]
+    fn presets_are_cumulative() {
+        let presets = [Action::Deny, Action::Read, Action::Modify, Action::Admin];
+        for pair in presets.windows(2) {
+            let (lower, higher) = (pair[0].capabilities(), pair[1].capabilities());
+            assert!(lower.len() < higher.len());
+            assert_eq!(lower.union(higher), higher);
+        }
+
+        assert!(Action::Deny.capabilities().is_empty());
+        assert_eq!(Action::Admin.capabilities(), Capabilities::ALL);
+    }
+
+    #[test]
+    fn drop_box() {
+        let permissions = vec![
+            permission!(path = "/inbox", action = Action::Deny, children = false),
+            permission!(
+                pattern = "inbox/*",
+                capabilities = [Capability::Create],
+                children = true,
+            ),
+        ];
+
+        let inbox = evaluate_permissions!(
+            default = Action::Read,
+            path = "/inbox/upload.pdf",
+            permissions = permissions.clone()
+        );
+        assert!(inbox.contains(Capability::Create));
+        assert!(!inbox.contains(Capability::List));
+        assert!(!inbox.contains(Capability::Read));
+        assert!(!inbox.contains(Capability::Overwrite));
+
+        let listing = evaluate_permissions!(
+            default = Action::Read,
+            path = "/inbox",
+            permissions = permissions
+        );
+        assert!(listing.is_empty());
+    }
+
+    #[test]
+    fn edit_without_delete() {
+        let permissions = vec![permission!(
+            path = "/docs",
+            capabilities = [
+                Capability::List,
+                Capability::Read,
+                Capability::Create,
+                Capability::Overwrite,
+            ],
+            children = true,
+        )];
+
+        let capabilities = evaluate_permissions!(
+            default = Action::Deny,
+            path = "/docs/report.odt",
+            permissions = permissions
+        );
+        assert!(capabilities.contains(Capability::Overwrite));
+        assert!(!capabilities.contains(Capability::Delete));
+    }
+
+    #[test]
+    fn group_capabilities_combine() {
+        let permissions = vec![
+            permission!(
+                group = "uploaders",
+                path = "/shared",
+                capabilities = [Capability::Create],
+                children = true,
+            ),
+            permission!(
+                group = "readers",
+                path = "/shared",
+                capabilities = [Capability::List, Capability::Read],
+                children = true,
+            ),
+        ];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Deny,
+                path = "/shared/file",
+                permissions = permissions
+            ),
+            Capabilities::from_iter([Capability::List, Capability::Read, Capability::Create])
+        );
+    }
+
+    #[test]
+    fn validity_window() {
+        use time::{Duration, OffsetDateTime};
+
+        let now = OffsetDateTime::now_utc();
+        let permissions = vec![
+            crate::database::Permission {
+                valid_until: Some(now - Duration::days(1)),
+                ..permission!(path = "/expired", action = Action::Modify, children = true)
+            },
+            crate::database::Permission {
+                valid_from: Some(now + Duration::days(1)),
+                ..permission!(path = "/upcoming", action = Action::Modify, children = true)
+            },
+            crate::database::Permission {
+                valid_from: Some(now - Duration::days(1)),
+                valid_until: Some(now + Duration::days(13)),
+                ..permission!(path = "/contract", action = Action::Modify, children = true)
+            },
+        ];
+
+        for (path, expected) in [
+            ("/expired/file", Action::Read),
+            ("/upcoming/file", Action::Read),
+            ("/contract/file", Action::Modify),
+        ] {
+            assert_eq!(
+                evaluate_permissions!(
+                    default = Action::Read,
+                    path = path,
+                    permissions = permissions.clone()
+                ),
+                expected.capabilities(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn expired_permission_does_not_shadow() {
+        use time::{Duration, OffsetDateTime};
+
+        let permissions = vec![
+            permission!(path = "/project", action = Action::Read, children = true),
+            crate::database::Permission {
+                valid_until: Some(OffsetDateTime::now_utc() - Duration::minutes(1)),
+                ..permission!(
+                    path = "/project/file",
+                    action = Action::Deny,
+                    children = false
+                )
+            },
+        ];
+
+        assert_eq!(
+            evaluate_permissions!(
+                default = Action::Deny,
+                path = "/project/file",
+                permissions = permissions
+            ),
+            Action::Read.capabilities()
+        );
+    }
+
+    #[test]
+    fn visibility_requires_list() {
+        let visibility = super::Visibility {
+            permissions: std::sync::Arc::new(vec![
+                permission!(
+                    path = "/inbox",
+                    capabilities = [Capability::Create],
+                    children = true
+                ),
+                permission!(path = "/public", action = Action::Read, children = true),
+            ]),
+            default: Action::Deny,
+        };
+
+        // Uploads to a drop box are allowed, but its contents stay hidden
+        assert!(!visibility.is_visible(std::path::Path::new("/inbox/upload.pdf")));
+        assert!(visibility.is_visible(std::path::Path::new("/public/report.pdf")));
+        assert!(!visibility.is_visible(std::path::Path::new("/private")));
+    }
 }
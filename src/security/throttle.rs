@@ -0,0 +1,366 @@
+use crate::config::Config;
+use async_graphql::{Enum, SimpleObject};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use time::{Duration, OffsetDateTime};
+
+/// The longest a username or address can be locked out for. Failures are also forgotten once
+/// nothing has failed for this long.
+const MAX_LOCKOUT: Duration = Duration::hours(1);
+
+/// Attached to a request when its credentials could not be checked because of a lockout, along
+/// with how long until attempts are allowed again
+#[derive(Clone, Copy, Debug)]
+pub struct LockedOut(pub Duration);
+
+/// What failed login attempts are counted against
+#[derive(Clone, Copy, Debug, Enum, Eq, Hash, PartialEq)]
+pub enum LockoutKind {
+    Username,
+    Address,
+    /// The slug of a public share
+    Share,
+}
+
+/// The failed login attempts for a username, address, or share
+#[derive(Clone, Debug, Eq, PartialEq, SimpleObject)]
+pub struct Lockout {
+    pub kind: LockoutKind,
+    /// The username, address, or share slug
+    pub key: String,
+    /// The number of consecutive failed attempts
+    pub failures: i32,
+    pub last_failure_at: OffsetDateTime,
+    /// When login attempts will be allowed again, if currently locked out
+    pub locked_until: Option<OffsetDateTime>,
+}
+
+impl Lockout {
+    /// Check if the failures are old enough to be forgotten
+    fn is_stale(&self, now: OffsetDateTime) -> bool {
+        self.locked_until.is_none_or(|until| until <= now)
+            && self.last_failure_at + MAX_LOCKOUT <= now
+    }
+}
+
+/// Tracks failed login attempts by username or share and address, locking them out with an exponential
+/// backoff once too many attempts have failed
+pub struct Throttle {
+    attempt_limit: u32,
+    lockout: Duration,
+    lockouts: Mutex<HashMap<(LockoutKind, String), Lockout>>,
+}
+
+impl Throttle {
+    /// Create a new throttle using the configured limits
+    pub fn new(config: &Config) -> Arc<Throttle> {
+        Arc::new(Throttle {
+            attempt_limit: config.login_attempt_limit,
+            lockout: config.lockout_duration,
+            lockouts: Mutex::default(),
+        })
+    }
+
+    /// Check if either the username or address is locked out, returning how long until attempts
+    /// are allowed again
+    pub fn check(&self, username: &str, address: IpAddr) -> Option<Duration> {
+        self.check_keys(keys(LockoutKind::Username, username, address))
+    }
+
+    /// Check if either the share or address is locked out, returning how long until attempts are
+    /// allowed again
+    pub fn check_share(&self, slug: &str, address: IpAddr) -> Option<Duration> {
+        self.check_keys(keys(LockoutKind::Share, slug, address))
+    }
+
+    fn check_keys(&self, keys: [(LockoutKind, String); 2]) -> Option<Duration> {
+        let now = OffsetDateTime::now_utc();
+        let lockouts = self.lockouts.lock().unwrap();
+
+        keys.iter()
+            .filter_map(|key| lockouts.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+            .map(|until| until - now)
+    }
+
+    /// Record a failed attempt against the username and address
+    pub fn failed(&self, username: &str, address: IpAddr) {
+        self.record_failure(keys(LockoutKind::Username, username, address));
+    }
+
+    /// Record a failed attempt to open a share against the share and address
+    pub fn share_failed(&self, slug: &str, address: IpAddr) {
+        self.record_failure(keys(LockoutKind::Share, slug, address));
+    }
+
+    fn record_failure(&self, keys: [(LockoutKind, String); 2]) {
+        let now = OffsetDateTime::now_utc();
+        let mut lockouts = self.lockouts.lock().unwrap();
+        lockouts.retain(|_, lockout| !lockout.is_stale(now));
+
+        for (kind, key) in keys {
+            let lockout = lockouts
+                .entry((kind, key.clone()))
+                .or_insert_with(|| Lockout {
+                    kind,
+                    key,
+                    failures: 0,
+                    last_failure_at: now,
+                    locked_until: None,
+                });
+
+            lockout.failures += 1;
+            lockout.last_failure_at = now;
+
+            // Every failure past the limit doubles the lockout
+            if let Some(excess) = (lockout.failures as u32).checked_sub(self.attempt_limit) {
+                let duration = 2i32
+                    .checked_pow(excess)
+                    .and_then(|factor| self.lockout.checked_mul(factor))
+                    .map_or(MAX_LOCKOUT, |duration| duration.min(MAX_LOCKOUT));
+                lockout.locked_until = Some(now + duration);
+            }
+        }
+    }
+
+    /// Forget the failed attempts for a username after a successful login, and halve those of the
+    /// address. Addresses shared by many users, such as behind NAT, recover as people log in,
+    /// while someone with a valid account still can't wipe out the failures of their guesses.
+    pub fn succeeded(&self, username: &str, address: IpAddr) {
+        self.record_success(keys(LockoutKind::Username, username, address));
+    }
+
+    /// Forget the failed attempts for a share after it was opened, and halve those of the address
+    pub fn share_succeeded(&self, slug: &str, address: IpAddr) {
+        self.record_success(keys(LockoutKind::Share, slug, address));
+    }
+
+    fn record_success(&self, [key, address]: [(LockoutKind, String); 2]) {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        lockouts.remove(&key);
+
+        if let Some(lockout) = lockouts.get_mut(&address) {
+            lockout.failures /= 2;
+            if lockout.failures == 0 {
+                lockouts.remove(&address);
+            }
+        }
+    }
+
+    /// Get all the usernames and addresses with failed attempts
+    pub fn list(&self) -> Vec<Lockout> {
+        let now = OffsetDateTime::now_utc();
+        let lockouts = self.lockouts.lock().unwrap();
+
+        let mut lockouts = lockouts
+            .values()
+            .filter(|lockout| !lockout.is_stale(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        lockouts.sort_by_key(|lockout| Reverse(lockout.last_failure_at));
+        lockouts
+    }
+
+    /// Clear the failed attempts for a username or address. Returns whether there were any.
+    pub fn clear(&self, kind: LockoutKind, key: &str) -> bool {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        lockouts.remove(&(kind, key.to_string())).is_some()
+    }
+}
+
+/// The keys an attempt is counted against
+fn keys(kind: LockoutKind, key: &str, address: IpAddr) -> [(LockoutKind, String); 2] {
+    [
+        (kind, key.to_string()),
+        (LockoutKind::Address, address.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockoutKind, Throttle, MAX_LOCKOUT};
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        sync::Mutex,
+    };
+    use time::Duration;
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn throttle() -> Throttle {
+        Throttle {
+            attempt_limit: 3,
+            lockout: Duration::minutes(1),
+            lockouts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get how long the username or address was locked out for by its last failure
+    fn lockout(throttle: &Throttle, kind: LockoutKind, key: &str) -> Option<Duration> {
+        let lockouts = throttle.lockouts.lock().unwrap();
+        let lockout = lockouts.get(&(kind, key.to_string()))?;
+        lockout
+            .locked_until
+            .map(|until| until - lockout.last_failure_at)
+    }
+
+    #[test]
+    fn allows_attempts_below_limit() {
+        let throttle = throttle();
+        throttle.failed("alice", ADDRESS);
+        throttle.failed("alice", ADDRESS);
+
+        assert_eq!(throttle.check("alice", ADDRESS), None);
+        assert_eq!(lockout(&throttle, LockoutKind::Username, "alice"), None);
+    }
+
+    #[test]
+    fn locks_out_at_limit() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.failed("alice", ADDRESS);
+        }
+
+        let remaining = throttle.check("alice", ADDRESS).unwrap();
+        assert!(remaining > Duration::ZERO && remaining <= Duration::minutes(1));
+        assert_eq!(
+            lockout(&throttle, LockoutKind::Username, "alice"),
+            Some(Duration::minutes(1))
+        );
+        assert_eq!(
+            lockout(&throttle, LockoutKind::Address, &ADDRESS.to_string()),
+            Some(Duration::minutes(1))
+        );
+    }
+
+    #[test]
+    fn lockout_grows_exponentially() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.failed("alice", ADDRESS);
+        }
+
+        for minutes in [2, 4, 8, 16] {
+            throttle.failed("alice", ADDRESS);
+            assert_eq!(
+                lockout(&throttle, LockoutKind::Username, "alice"),
+                Some(Duration::minutes(minutes))
+            );
+        }
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let throttle = throttle();
+        for _ in 0..100 {
+            throttle.failed("alice", ADDRESS);
+        }
+
+        assert_eq!(
+            lockout(&throttle, LockoutKind::Username, "alice"),
+            Some(MAX_LOCKOUT)
+        );
+        assert!(throttle.check("alice", ADDRESS).unwrap() <= MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn locks_out_username_from_any_address() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.failed("alice", ADDRESS);
+        }
+
+        assert!(throttle.check("alice", OTHER_ADDRESS).is_some());
+        assert!(throttle.check("bob", ADDRESS).is_some());
+        assert_eq!(throttle.check("bob", OTHER_ADDRESS), None);
+    }
+
+    #[test]
+    fn success_resets_username() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.failed("alice", ADDRESS);
+        }
+        throttle.succeeded("alice", OTHER_ADDRESS);
+
+        assert_eq!(throttle.check("alice", OTHER_ADDRESS), None);
+        assert_eq!(lockout(&throttle, LockoutKind::Username, "alice"), None);
+
+        // Failures start counting from zero again
+        throttle.failed("alice", OTHER_ADDRESS);
+        assert_eq!(throttle.check("alice", OTHER_ADDRESS), None);
+    }
+
+    #[test]
+    fn success_decays_address_failures() {
+        let throttle = throttle();
+        throttle.failed("alice", ADDRESS);
+        throttle.failed("bob", ADDRESS);
+        throttle.succeeded("carol", ADDRESS);
+
+        // Only one failure is left against the address, so it takes two more to lock it out
+        throttle.failed("dave", ADDRESS);
+        assert_eq!(throttle.check("mallory", ADDRESS), None);
+        throttle.failed("erin", ADDRESS);
+        assert!(throttle.check("mallory", ADDRESS).is_some());
+    }
+
+    #[test]
+    fn success_forgets_single_address_failure() {
+        let throttle = throttle();
+        throttle.failed("alice", ADDRESS);
+        throttle.succeeded("bob", ADDRESS);
+
+        assert!(throttle
+            .list()
+            .iter()
+            .all(|l| l.kind != LockoutKind::Address));
+    }
+
+    #[test]
+    fn shared_address_is_not_locked_out() {
+        let throttle = throttle();
+        for i in 0..20 {
+            throttle.failed(&format!("user{i}"), ADDRESS);
+            throttle.succeeded(&format!("user{i}"), ADDRESS);
+        }
+
+        assert_eq!(throttle.check("mallory", ADDRESS), None);
+    }
+
+    #[test]
+    fn shares_are_separate_from_usernames() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.share_failed("alice", OTHER_ADDRESS);
+        }
+
+        assert!(throttle.check_share("alice", ADDRESS).is_some());
+        assert_eq!(throttle.check("alice", ADDRESS), None);
+
+        throttle.share_succeeded("alice", ADDRESS);
+        assert_eq!(throttle.check_share("alice", ADDRESS), None);
+        assert!(throttle.check_share("alice", OTHER_ADDRESS).is_some());
+    }
+
+    #[test]
+    fn clear_resets_lockout() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.failed("alice", ADDRESS);
+        }
+
+        assert!(throttle.clear(LockoutKind::Username, "alice"));
+        assert!(throttle.clear(LockoutKind::Address, &ADDRESS.to_string()));
+        assert!(!throttle.clear(LockoutKind::Username, "alice"));
+        assert_eq!(throttle.check("alice", ADDRESS), None);
+        assert!(throttle.list().is_empty());
+    }
+}
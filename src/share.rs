@@ -0,0 +1,429 @@
+use crate::{
+    config::Config,
+    database::{Capability, Share},
+    error::{Error, Result},
+    security::{
+        check_permissions, client_address, sanitize_path, throttle::Throttle, PermissionCache,
+        Visibility,
+    },
+};
+use axum::{
+    body::StreamBody,
+    extract::{ConnectInfo, Path as UrlPath},
+    headers::{
+        authorization::{Authorization, Basic},
+        HeaderMapExt,
+    },
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Response},
+    Extension,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use sqlx::PgPool;
+use std::{
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs::{self, File};
+use tokio_stream::{wrappers::ReadDirStream, StreamExt};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+/// Characters that must be escaped within a single path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Serve the root of a share
+pub async fn root(
+    UrlPath(slug): UrlPath<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(db): Extension<PgPool>,
+    Extension(cache): Extension<Arc<PermissionCache>>,
+    Extension(throttle): Extension<Arc<Throttle>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let address = client_address(&config, peer.ip().to_canonical(), &headers);
+    serve(
+        &config,
+        &db,
+        &cache,
+        &throttle,
+        address,
+        &headers,
+        &slug,
+        PathBuf::new(),
+    )
+    .await
+}
+
+/// Serve an item within a shared folder
+pub async fn nested(
+    UrlPath((slug, path)): UrlPath<(String, String)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(db): Extension<PgPool>,
+    Extension(cache): Extension<Arc<PermissionCache>>,
+    Extension(throttle): Extension<Arc<Throttle>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let address = client_address(&config, peer.ip().to_canonical(), &headers);
+    let sub_path = sanitize_path(PathBuf::from(path))?;
+    serve(
+        &config, &db, &cache, &throttle, address, &headers, &slug, sub_path,
+    )
+    .await
+}
+
+/// Serve a file or read-only directory listing from a share. Anything that cannot be accessed is
+/// reported as not found so the existence of shares is not leaked. Unknown slugs and wrong
+/// passwords count as failed attempts against the slug and client address, the same as logins.
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    config: &Config,
+    db: &PgPool,
+    cache: &PermissionCache,
+    throttle: &Throttle,
+    address: IpAddr,
+    headers: &HeaderMap,
+    slug: &str,
+    sub_path: PathBuf,
+) -> Result<Response> {
+    if let Some(retry_after) = throttle.check_share(slug, address) {
+        warn!(%slug, %address, "rejected share access while locked out");
+        return Err(Error::TooManyRequests(retry_after));
+    }
+
+    let share = match Share::get_by_slug(db, slug)
+        .await?
+        .filter(Share::is_available)
+    {
+        Some(share) => share,
+        None => {
+            throttle.share_failed(slug, address);
+            return Err(Error::NotFound);
+        }
+    };
+
+    // Password protected shares use the password from HTTP basic authentication. Requests without
+    // one are not counted, as browsers only prompt for the password after being rejected.
+    let credentials = headers.typed_get::<Authorization<Basic>>();
+    let password = credentials.as_ref().map(|c| c.password());
+    if !share.password_valid(password) {
+        if password.is_some() {
+            warn!(%slug, %address, "failed share password attempt");
+            throttle.share_failed(slug, address);
+        }
+        return Err(Error::Unauthorized);
+    }
+    throttle.share_succeeded(slug, address);
+
+    // The share only grants what its creator is still allowed to read
+    let owner = cache.user(db, &share.owner).await?.ok_or(Error::NotFound)?;
+    let path = Path::new(&share.path)
+        .components()
+        .chain(sub_path.components())
+        .collect::<PathBuf>();
+    check_permissions(db, cache, &owner, &path, Capability::Read)
+        .await
+        .map_err(|_| Error::NotFound)?;
+
+    let full_path = config.path.join(&path);
+    let meta = fs::metadata(&full_path).await?;
+
+    if meta.is_dir() {
+        let visibility = Visibility::load(db, cache, &owner).await?;
+        let listing = render_listing(&share, &full_path, &path, &sub_path, &visibility).await?;
+        Ok(Html(listing).into_response())
+    } else {
+        if !share.record_download(db).await? {
+            return Err(Error::NotFound);
+        }
+
+        let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
+        let file = File::open(&full_path).await?;
+        let response = (
+            [
+                (header::CONTENT_TYPE, mime.to_string()),
+                (header::CONTENT_LENGTH, meta.len().to_string()),
+            ],
+            StreamBody::new(ReaderStream::new(file)),
+        );
+        Ok(response.into_response())
+    }
+}
+
+/// Render a basic HTML page listing the visible contents of a shared directory
+async fn render_listing(
+    share: &Share,
+    full_path: &Path,
+    path: &Path,
+    sub_path: &Path,
+    visibility: &Visibility,
+) -> Result<String> {
+    let read_dir = fs::read_dir(full_path).await?;
+    let mut stream = ReadDirStream::new(read_dir);
+
+    let mut entries = Vec::new();
+    while let Some(entry) = stream.next().await {
+        let entry = entry?;
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !visibility.is_visible(&path.join(&name)) {
+            continue;
+        }
+
+        let is_dir = entry.file_type().await?.is_dir();
+        entries.push((!is_dir, name));
+    }
+    entries.sort();
+
+    // Build the link to the current directory within the share
+    let mut base = format!("/s/{}/", share.slug);
+    for component in sub_path.iter() {
+        let segment = component.to_string_lossy();
+        write!(base, "{}/", utf8_percent_encode(&segment, SEGMENT)).unwrap();
+    }
+
+    let title = match path.file_name() {
+        Some(name) => escape(&name.to_string_lossy()),
+        None => String::from("Shared files"),
+    };
+
+    let mut page = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+        <body><h1>{title}</h1><ul>"
+    );
+    if sub_path.parent().is_some() {
+        page.push_str("<li><a href=\"../\">../</a></li>");
+    }
+    for (is_file, name) in entries {
+        let suffix = if is_file { "" } else { "/" };
+        write!(
+            page,
+            "<li><a href=\"{}{}{}\">{}{}</a></li>",
+            base,
+            utf8_percent_encode(&name, SEGMENT),
+            suffix,
+            escape(&name),
+            suffix
+        )
+        .unwrap();
+    }
+    page.push_str("</ul></body></html>");
+
+    Ok(page)
+}
+
+/// Escape text for inclusion in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::{
+        config::Config,
+        database::{self, Share, User},
+        error::Error,
+        security::{throttle::Throttle, PermissionCache},
+    };
+    use axum::{
+        headers::{authorization::Authorization, HeaderMapExt},
+        http::{HeaderMap, StatusCode},
+    };
+    use sqlx::PgPool;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        path::PathBuf,
+        sync::Arc,
+    };
+    use time::{Duration, OffsetDateTime};
+    use tokio::fs;
+    use uuid::Uuid;
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    struct Fixture {
+        config: Config,
+        db: PgPool,
+        cache: Arc<PermissionCache>,
+        throttle: Arc<Throttle>,
+        owner: User,
+    }
+
+    impl Fixture {
+        /// Create a user with a shareable file in a fresh directory
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let username = format!("share-{}", Uuid::new_v4());
+            let owner = User::create_if_not_exists(&db, &username, "Owner")
+                .await
+                .unwrap();
+
+            let path = std::env::temp_dir().join(&username);
+            fs::create_dir_all(&path).await.unwrap();
+            fs::write(path.join("shared.txt"), "hello").await.unwrap();
+
+            let config = Config {
+                path,
+                ..Config::default()
+            };
+            let throttle = Throttle::new(&config);
+
+            Some(Fixture {
+                config,
+                db,
+                cache: PermissionCache::new(),
+                throttle,
+                owner,
+            })
+        }
+
+        async fn share(&self, password: Option<&str>, expires_at: Option<OffsetDateTime>) -> Share {
+            self.owner
+                .create_share(&self.db, "shared.txt", password, expires_at, None)
+                .await
+                .unwrap()
+        }
+
+        async fn get(
+            &self,
+            slug: &str,
+            address: IpAddr,
+            password: Option<&str>,
+        ) -> Result<StatusCode, Error> {
+            let mut headers = HeaderMap::new();
+            if let Some(password) = password {
+                headers.typed_insert(Authorization::basic("", password));
+            }
+
+            let response = serve(
+                &self.config,
+                &self.db,
+                &self.cache,
+                &self.throttle,
+                address,
+                &headers,
+                slug,
+                PathBuf::new(),
+            )
+            .await?;
+            Ok(response.status())
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.config.path);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_share_without_password() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let share = fixture.share(None, None).await;
+
+        let status = fixture.get(&share.slug, ADDRESS, None).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requires_password() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let share = fixture.share(Some("hunter2"), None).await;
+
+        let missing = fixture.get(&share.slug, ADDRESS, None).await;
+        assert!(matches!(missing, Err(Error::Unauthorized)));
+        let wrong = fixture.get(&share.slug, ADDRESS, Some("hunter3")).await;
+        assert!(matches!(wrong, Err(Error::Unauthorized)));
+
+        let status = fixture
+            .get(&share.slug, ADDRESS, Some("hunter2"))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn wrong_passwords_lock_out_share() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let share = fixture.share(Some("hunter2"), None).await;
+
+        // Asking without a password is how browsers prompt for one, so it is never counted
+        for _ in 0..fixture.config.login_attempt_limit {
+            let result = fixture.get(&share.slug, ADDRESS, None).await;
+            assert!(matches!(result, Err(Error::Unauthorized)));
+        }
+        for _ in 0..fixture.config.login_attempt_limit {
+            let result = fixture.get(&share.slug, ADDRESS, Some("hunter3")).await;
+            assert!(matches!(result, Err(Error::Unauthorized)));
+        }
+
+        // The correct password is not checked while locked out, from any address
+        for address in [ADDRESS, OTHER_ADDRESS] {
+            let result = fixture.get(&share.slug, address, Some("hunter2")).await;
+            assert!(matches!(result, Err(Error::TooManyRequests(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_share_is_not_found() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let expired = OffsetDateTime::now_utc() - Duration::minutes(1);
+        let share = fixture.share(None, Some(expired)).await;
+
+        let result = fixture.get(&share.slug, ADDRESS, None).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+
+        let future = OffsetDateTime::now_utc() + Duration::days(1);
+        let share = fixture.share(None, Some(future)).await;
+        let status = fixture.get(&share.slug, ADDRESS, None).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unknown_slugs_lock_out_address() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let share = fixture.share(None, None).await;
+
+        for _ in 0..fixture.config.login_attempt_limit {
+            let slug = Uuid::new_v4().to_string();
+            let result = fixture.get(&slug, ADDRESS, None).await;
+            assert!(matches!(result, Err(Error::NotFound)));
+        }
+
+        // Guessing slugs locks out the address, but not the share for everyone else
+        let result = fixture.get(&share.slug, ADDRESS, None).await;
+        assert!(matches!(result, Err(Error::TooManyRequests(_))));
+        let status = fixture.get(&share.slug, OTHER_ADDRESS, None).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+}
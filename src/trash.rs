@@ -0,0 +1,343 @@
+use crate::{
+    config::Config,
+    database::{FileUsage, FolderQuota, Property, TrashedItem, User},
+    error::{Error, Result},
+    webdav::disk_usage,
+};
+use sqlx::PgPool;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio::{fs, time::interval};
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+/// The hidden directory within the base path where deleted items are kept
+pub const TRASH_DIR: &str = ".davoxide-trash";
+
+/// How often to check for items that have been in the trash for too long
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Move a file or folder into the trash instead of deleting it
+pub async fn move_to_trash(
+    db: &PgPool,
+    base: &Path,
+    user: &User,
+    path: &Path,
+) -> Result<TrashedItem> {
+    // The base directory itself can never be deleted
+    if path.as_os_str().is_empty() {
+        return Err(Error::InvalidPermissions);
+    }
+
+    let meta = fs::symlink_metadata(base.join(path)).await?;
+    let size = disk_usage(&base.join(path)).await?;
+
+    let name = Uuid::new_v4().to_string();
+    fs::create_dir_all(base.join(TRASH_DIR)).await?;
+    fs::rename(base.join(path), trashed_path(base, &name)).await?;
+
+    // Keep the properties and usage with the item so they come back when it is restored. Trashed
+    // items do not count against any quota until then.
+    let (original, trashed) = (path.to_string_lossy(), trashed_key(&name));
+    Property::move_within(db, &original, &trashed).await?;
+    FileUsage::move_within(db, &original, &trashed).await?;
+    FolderQuota::adjust_usage(db, &original, -(size as i64)).await?;
+
+    let item = TrashedItem::create(db, &original, &name, meta.is_dir(), &user.username).await?;
+    Ok(item)
+}
+
+/// Move an item out of the trash and back to where it was deleted from. Returns `false`, leaving
+/// the item in the trash, if the original location has been reused in the meantime.
+pub async fn restore(db: &PgPool, base: &Path, item: &TrashedItem) -> Result<bool> {
+    if fs::try_exists(base.join(&item.original_path)).await? {
+        return Ok(false);
+    }
+
+    put_back(db, base, item).await?;
+    Ok(true)
+}
+
+/// Move an item from the trash to its original location, along with its properties and usage
+async fn put_back(db: &PgPool, base: &Path, item: &TrashedItem) -> Result<()> {
+    let destination = base.join(&item.original_path);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(trashed_path(base, &item.name), &destination).await?;
+    let size = disk_usage(&destination).await?;
+
+    let trashed = trashed_key(&item.name);
+    Property::move_within(db, &trashed, &item.original_path).await?;
+    FileUsage::move_within(db, &trashed, &item.original_path).await?;
+    FolderQuota::adjust_usage(db, &item.original_path, size as i64).await?;
+
+    TrashedItem::delete(db, item.id).await?;
+    Ok(())
+}
+
+/// Put back an item that was going to be replaced by a copy or move that failed, removing anything
+/// the failed operation left in its place
+pub async fn restore_replaced(db: &PgPool, base: &Path, item: &TrashedItem) -> Result<()> {
+    let partial = base.join(&item.original_path);
+    let result = match fs::symlink_metadata(&partial).await {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&partial).await,
+        Ok(_) => fs::remove_file(&partial).await,
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    result?;
+
+    Property::delete_within(db, &item.original_path).await?;
+    FileUsage::delete_within(db, &item.original_path).await?;
+
+    put_back(db, base, item).await
+}
+
+/// Permanently remove an item from the trash
+pub async fn purge(db: &PgPool, base: &Path, item: &TrashedItem) -> Result<()> {
+    let path = trashed_path(base, &item.name);
+    let result = match item.is_directory {
+        true => fs::remove_dir_all(&path).await,
+        false => fs::remove_file(&path).await,
+    };
+    match result {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let trashed = trashed_key(&item.name);
+    Property::delete_within(db, &trashed).await?;
+    FileUsage::delete_within(db, &trashed).await?;
+
+    TrashedItem::delete(db, item.id).await?;
+    Ok(())
+}
+
+/// Periodically purge anything that has been in the trash for longer than the retention period
+#[instrument(skip_all)]
+pub async fn cleanup(db: PgPool, config: Arc<Config>) {
+    let mut interval = interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let cutoff = OffsetDateTime::now_utc() - config.trash_retention;
+        let expired = match TrashedItem::deleted_before(&db, cutoff).await {
+            Ok(expired) => expired,
+            Err(e) => {
+                error!(error = %e, "failed to find expired items in the trash");
+                continue;
+            }
+        };
+
+        for item in &expired {
+            if let Err(e) = purge(&db, &config.path, item).await {
+                error!(error = %e, id = item.id, "failed to purge item from the trash");
+            }
+        }
+
+        if !expired.is_empty() {
+            info!(count = expired.len(), "purged expired items from the trash");
+        }
+    }
+}
+
+/// Get where an item is stored on disk while in the trash
+fn trashed_path(base: &Path, name: &str) -> PathBuf {
+    base.join(TRASH_DIR).join(name)
+}
+
+/// Get the key the properties and usage of an item are stored under while in the trash
+fn trashed_key(name: &str) -> String {
+    format!("{TRASH_DIR}/{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{move_to_trash, purge, restore, trashed_key, trashed_path};
+    use crate::{
+        database::{self, FileUsage, TrashedItem, User},
+        error::Error,
+    };
+    use sqlx::PgPool;
+    use std::path::{Path, PathBuf};
+    use tokio::fs;
+    use uuid::Uuid;
+
+    struct Fixture {
+        db: PgPool,
+        base: PathBuf,
+        user: User,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let username = format!("trash-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &username, "Deleter")
+                .await
+                .unwrap();
+
+            let base = std::env::temp_dir().join(&username);
+            fs::create_dir_all(&base).await.unwrap();
+
+            Some(Fixture { db, base, user })
+        }
+
+        /// Write a file and record it as uploaded by the user. Paths are kept within a folder
+        /// named after the user, as usage is recorded by path.
+        async fn upload(&self, name: &str, contents: &str) -> PathBuf {
+            let path = Path::new(&self.user.username).join(name);
+            let full_path = self.base.join(&path);
+            fs::create_dir_all(full_path.parent().unwrap())
+                .await
+                .unwrap();
+            fs::write(&full_path, contents).await.unwrap();
+
+            let key = path.to_string_lossy();
+            FileUsage::record(&self.db, &key, &self.user.username, contents.len() as i64)
+                .await
+                .unwrap();
+            path
+        }
+
+        async fn read(&self, path: &Path) -> String {
+            fs::read_to_string(self.base.join(path)).await.unwrap()
+        }
+
+        async fn exists(&self, path: &Path) -> bool {
+            fs::try_exists(self.base.join(path)).await.unwrap()
+        }
+
+        async fn usage(&self, path: &str) -> Option<i64> {
+            let usage = FileUsage::get(&self.db, path).await.unwrap();
+            usage.map(|usage| usage.size)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[tokio::test]
+    async fn move_to_trash_keeps_item() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let path = fixture.upload("notes.txt", "hello").await;
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+
+        let item = move_to_trash(db, base, user, &path).await.unwrap();
+        assert_eq!(item.original_path, path.to_string_lossy());
+        assert_eq!(item.deleted_by.as_ref(), Some(&user.username));
+        assert!(!item.is_directory);
+
+        assert!(!fixture.exists(&path).await);
+        let contents = fs::read_to_string(trashed_path(base, &item.name)).await;
+        assert_eq!(contents.unwrap(), "hello");
+
+        // The usage moves with the item, which stops it counting against the user's quota
+        assert_eq!(fixture.usage(&path.to_string_lossy()).await, None);
+        assert_eq!(fixture.usage(&trashed_key(&item.name)).await, Some(5));
+        assert_eq!(FileUsage::total_for(db, &user.username).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn base_directory_cannot_be_trashed() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+
+        let result = move_to_trash(db, base, user, Path::new("")).await;
+        assert!(matches!(result, Err(Error::InvalidPermissions)));
+        let result = move_to_trash(db, base, user, Path::new("missing")).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn restore_puts_item_back() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let path = fixture.upload("folder/notes.txt", "hello").await;
+        let folder = path.parent().unwrap();
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+
+        // Deleting a folder keeps its contents together
+        let item = move_to_trash(db, base, user, folder).await.unwrap();
+        assert!(item.is_directory);
+        assert!(!fixture.exists(folder).await);
+
+        assert!(restore(db, base, &item).await.unwrap());
+        assert_eq!(fixture.read(&path).await, "hello");
+        assert_eq!(fixture.usage(&path.to_string_lossy()).await, Some(5));
+        assert_eq!(TrashedItem::get(db, item.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn restore_does_not_replace_reused_location() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let path = fixture.upload("notes.txt", "first").await;
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+
+        let first = move_to_trash(db, base, user, &path).await.unwrap();
+        fixture.upload("notes.txt", "second").await;
+        let second = move_to_trash(db, base, user, &path).await.unwrap();
+        fixture.upload("notes.txt", "third").await;
+
+        // Items deleted from the same location are kept apart in the trash
+        assert_ne!(first.name, second.name);
+
+        assert!(!restore(db, base, &first).await.unwrap());
+        assert_eq!(fixture.read(&path).await, "third");
+        assert_eq!(fixture.usage(&path.to_string_lossy()).await, Some(5));
+        assert_eq!(TrashedItem::get(db, first.id).await.unwrap(), Some(first));
+
+        // Once the location is free again, either can be restored
+        fs::remove_file(base.join(&path)).await.unwrap();
+        assert!(restore(db, base, &second).await.unwrap());
+        assert_eq!(fixture.read(&path).await, "second");
+        assert_eq!(fixture.usage(&path.to_string_lossy()).await, Some(6));
+    }
+
+    #[tokio::test]
+    async fn purge_removes_item() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let file = fixture.upload("notes.txt", "hello").await;
+        let folder = fixture.upload("folder/nested/notes.txt", "hello").await;
+        let folder = folder.parent().unwrap().parent().unwrap();
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+
+        for path in [file.as_path(), folder] {
+            let item = move_to_trash(db, base, user, path).await.unwrap();
+            purge(db, base, &item).await.unwrap();
+
+            assert!(!fs::try_exists(trashed_path(base, &item.name))
+                .await
+                .unwrap());
+            assert_eq!(TrashedItem::get(db, item.id).await.unwrap(), None);
+            let key = trashed_key(&item.name);
+            assert_eq!(
+                fixture.usage(&format!("{key}/nested/notes.txt")).await,
+                None
+            );
+            assert_eq!(fixture.usage(&key).await, None);
+
+            // Purging what is already gone only forgets about it
+            purge(db, base, &item).await.unwrap();
+        }
+    }
+}
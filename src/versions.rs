@@ -0,0 +1,327 @@
+use crate::{
+    config::Config,
+    database::{FileUsage, FileVersion, FolderQuota, User},
+    error::Result,
+};
+use sqlx::PgPool;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use time::OffsetDateTime;
+use tokio::{fs, time::interval};
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+/// The hidden directory within the base path where previous versions of files are kept
+pub const VERSIONS_DIR: &str = ".davoxide-versions";
+
+/// How often to check for versions that have been kept for too long
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Keep a copy of the file's current content before it is overwritten. Returns [`None`] if there
+/// is nothing to keep.
+pub async fn snapshot(
+    db: &PgPool,
+    config: &Config,
+    user: &User,
+    path: &Path,
+) -> Result<Option<FileVersion>> {
+    if config.version_limit == 0 {
+        return Ok(None);
+    }
+
+    let full_path = config.path.join(path);
+    let meta = match fs::metadata(&full_path).await {
+        Ok(meta) if meta.is_file() => meta,
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let name = Uuid::new_v4().to_string();
+    fs::create_dir_all(config.path.join(VERSIONS_DIR)).await?;
+    fs::copy(&full_path, stored_path(&config.path, &name)).await?;
+
+    let last_modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH).into();
+    let version = FileVersion::create(
+        db,
+        &path.to_string_lossy(),
+        &name,
+        meta.len() as i64,
+        last_modified,
+        &user.username,
+    )
+    .await?;
+
+    Ok(Some(version))
+}
+
+/// Put back the content a file had when the version was taken, discarding what replaced it
+pub async fn revert(base: &Path, version: &FileVersion) -> Result<()> {
+    fs::copy(stored_path(base, &version.name), base.join(&version.path)).await?;
+    Ok(())
+}
+
+/// Replace the file's content with a previous version. The current content is kept as a new
+/// version, so restoring can be undone.
+pub async fn restore(
+    db: &PgPool,
+    config: &Config,
+    user: &User,
+    version: &FileVersion,
+) -> Result<()> {
+    let path = Path::new(&version.path);
+    snapshot(db, config, user, path).await?;
+
+    let full_path = config.path.join(path);
+    let replaced = match fs::metadata(&full_path).await {
+        Ok(meta) if meta.is_file() => meta.len() as i64,
+        _ => 0,
+    };
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::copy(stored_path(&config.path, &version.name), &full_path).await?;
+    FileUsage::record(db, &version.path, &user.username, version.size).await?;
+    FolderQuota::adjust_usage(db, &version.path, version.size - replaced).await?;
+
+    prune(db, config, path).await
+}
+
+/// Remove the versions of a file that exceed the configured count or age
+pub async fn prune(db: &PgPool, config: &Config, path: &Path) -> Result<()> {
+    let cutoff = OffsetDateTime::now_utc() - config.version_retention;
+
+    let versions = FileVersion::list(db, &path.to_string_lossy()).await?;
+    for (i, version) in versions.iter().enumerate() {
+        if i >= config.version_limit || version.created_at < cutoff {
+            remove(db, &config.path, version).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Permanently remove a version
+pub async fn remove(db: &PgPool, base: &Path, version: &FileVersion) -> Result<()> {
+    match fs::remove_file(stored_path(base, &version.name)).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    FileVersion::delete(db, version.id).await?;
+    Ok(())
+}
+
+/// Periodically remove any versions that have been kept for longer than the retention period
+#[instrument(skip_all)]
+pub async fn cleanup(db: PgPool, config: Arc<Config>) {
+    let mut interval = interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let cutoff = OffsetDateTime::now_utc() - config.version_retention;
+        let expired = match FileVersion::created_before(&db, cutoff).await {
+            Ok(expired) => expired,
+            Err(e) => {
+                error!(error = %e, "failed to find expired versions");
+                continue;
+            }
+        };
+
+        for version in &expired {
+            if let Err(e) = remove(&db, &config.path, version).await {
+                error!(error = %e, id = version.id, "failed to remove expired version");
+            }
+        }
+
+        if !expired.is_empty() {
+            info!(count = expired.len(), "removed expired versions");
+        }
+    }
+}
+
+/// Get where a version is stored on disk
+fn stored_path(base: &Path, name: &str) -> PathBuf {
+    base.join(VERSIONS_DIR).join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune, revert, snapshot, stored_path};
+    use crate::{
+        config::Config,
+        database::{self, FileVersion, User},
+    };
+    use sqlx::PgPool;
+    use std::path::{Path, PathBuf};
+    use time::Duration;
+    use tokio::fs;
+    use uuid::Uuid;
+
+    struct Fixture {
+        db: PgPool,
+        config: Config,
+        user: User,
+        /// A file within a folder only used by this test, as versions are recorded by path
+        path: PathBuf,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let username = format!("versions-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &username, "Editor")
+                .await
+                .unwrap();
+
+            let base = std::env::temp_dir().join(&username);
+            fs::create_dir_all(base.join(&username)).await.unwrap();
+            let config = Config {
+                path: base,
+                version_limit: 2,
+                ..Config::default()
+            };
+
+            let path = Path::new(&username).join("notes.txt");
+            Some(Fixture {
+                db,
+                config,
+                user,
+                path,
+            })
+        }
+
+        async fn write(&self, contents: &str) {
+            let full_path = self.config.path.join(&self.path);
+            fs::write(full_path, contents).await.unwrap();
+        }
+
+        async fn read(&self) -> String {
+            let full_path = self.config.path.join(&self.path);
+            fs::read_to_string(full_path).await.unwrap()
+        }
+
+        async fn snapshot(&self) -> Option<FileVersion> {
+            let (db, config, user) = (&self.db, &self.config, &self.user);
+            snapshot(db, config, user, &self.path).await.unwrap()
+        }
+
+        async fn versions(&self) -> Vec<FileVersion> {
+            FileVersion::list(&self.db, &self.path.to_string_lossy())
+                .await
+                .unwrap()
+        }
+
+        async fn is_stored(&self, version: &FileVersion) -> bool {
+            let path = stored_path(&self.config.path, &version.name);
+            fs::try_exists(path).await.unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.config.path);
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_keeps_copy() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.write("first").await;
+
+        let version = fixture.snapshot().await.unwrap();
+        assert_eq!(version.path, fixture.path.to_string_lossy());
+        assert_eq!(version.size, 5);
+        assert_eq!(version.created_by.as_ref(), Some(&fixture.user.username));
+        assert_eq!(fixture.versions().await, vec![version.clone()]);
+
+        let stored = stored_path(&fixture.config.path, &version.name);
+        assert_eq!(fs::read_to_string(stored).await.unwrap(), "first");
+    }
+
+    #[tokio::test]
+    async fn snapshot_skips_what_cannot_be_kept() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+
+        // Nothing to keep for new files or folders
+        assert_eq!(fixture.snapshot().await, None);
+        fs::create_dir(fixture.config.path.join(&fixture.path))
+            .await
+            .unwrap();
+        assert_eq!(fixture.snapshot().await, None);
+
+        fs::remove_dir(fixture.config.path.join(&fixture.path))
+            .await
+            .unwrap();
+        fixture.write("first").await;
+        fixture.config.version_limit = 0;
+        assert_eq!(fixture.snapshot().await, None);
+        assert!(fixture.versions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn revert_puts_back_content() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.write("first").await;
+        let version = fixture.snapshot().await.unwrap();
+        fixture.write("partial").await;
+
+        revert(&fixture.config.path, &version).await.unwrap();
+        assert_eq!(fixture.read().await, "first");
+
+        // The version is still kept until it is removed
+        assert!(fixture.is_stored(&version).await);
+        assert_eq!(fixture.versions().await, vec![version]);
+    }
+
+    #[tokio::test]
+    async fn prune_keeps_newest_versions() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+
+        let mut versions = Vec::new();
+        for contents in ["first", "second", "third"] {
+            fixture.write(contents).await;
+            versions.push(fixture.snapshot().await.unwrap());
+        }
+
+        prune(&fixture.db, &fixture.config, &fixture.path)
+            .await
+            .unwrap();
+
+        let kept = fixture.versions().await;
+        assert_eq!(kept, vec![versions[2].clone(), versions[1].clone()]);
+        assert!(!fixture.is_stored(&versions[0]).await);
+        assert!(fixture.is_stored(&versions[1]).await);
+        assert!(fixture.is_stored(&versions[2]).await);
+    }
+
+    #[tokio::test]
+    async fn prune_removes_expired_versions() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.write("first").await;
+        let version = fixture.snapshot().await.unwrap();
+
+        fixture.config.version_retention = Duration::ZERO;
+        prune(&fixture.db, &fixture.config, &fixture.path)
+            .await
+            .unwrap();
+
+        assert!(fixture.versions().await.is_empty());
+        assert!(!fixture.is_stored(&version).await);
+    }
+}
@@ -0,0 +1,400 @@
+use super::quota::Quota;
+use crate::{
+    database::{FileUsage, FileVersion, Property},
+    security::Visibility,
+};
+use axum::http::StatusCode;
+use bytes::{Buf, Bytes};
+use dav_server::{
+    davpath::DavPath,
+    fs::{
+        DavDirEntry, DavFile, DavFileSystem, DavMetaData, DavProp, FsError, FsFuture, FsStream,
+        OpenOptions, ReadDirMeta,
+    },
+};
+use sqlx::PgPool;
+use std::{
+    ffi::OsStr,
+    future::{self, Future},
+    io::SeekFrom,
+    os::unix::ffi::OsStrExt,
+    pin::Pin,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio_stream::StreamExt;
+use tracing::error;
+
+/// Wraps a file system to hide any directory entries the user is not allowed to see, and to keep
+/// the dead properties and storage usage in the database up to date
+#[derive(Clone)]
+pub struct FilteredFs {
+    inner: Box<dyn DavFileSystem>,
+    visibility: Arc<Visibility>,
+    db: PgPool,
+    /// The user any copied files are counted against
+    owner: String,
+    /// The quota reported to clients
+    quota: Option<Quota>,
+    /// The most bytes that can be written to files before running out of space, if limited
+    write_limit: Option<u64>,
+}
+
+impl FilteredFs {
+    /// Wrap the file system with the user's visibility rules and quota
+    pub fn new(
+        inner: Box<dyn DavFileSystem>,
+        visibility: Visibility,
+        db: PgPool,
+        owner: String,
+        quota: Option<Quota>,
+        write_limit: Option<u64>,
+    ) -> Box<FilteredFs> {
+        Box::new(FilteredFs {
+            inner,
+            visibility: Arc::new(visibility),
+            db,
+            owner,
+            quota,
+            write_limit,
+        })
+    }
+}
+
+impl DavFileSystem for FilteredFs {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        Box::pin(async move {
+            let write = options.write;
+            let file = self.inner.open(path, options).await?;
+
+            match self.write_limit {
+                Some(remaining) if write => Ok(Box::new(LimitedFile {
+                    inner: file,
+                    remaining,
+                }) as Box<dyn DavFile>),
+                _ => Ok(file),
+            }
+        })
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
+        Box::pin(async move {
+            let entries = self.inner.read_dir(path, meta).await?;
+
+            let parent = path.as_rel_ospath().to_path_buf();
+            let visibility = self.visibility.clone();
+            let filtered = entries.filter(move |entry| {
+                let name = entry.name();
+                visibility.is_visible(&parent.join(OsStr::from_bytes(&name)))
+            });
+
+            Ok(Box::pin(filtered) as FsStream<Box<dyn DavDirEntry>>)
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        self.inner.create_dir(path)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.inner.remove_dir(path).await?;
+
+            let path = property_path(path);
+            Property::delete_within(&self.db, &path)
+                .await
+                .map_err(storage_error)?;
+            FileUsage::delete_within(&self.db, &path)
+                .await
+                .map_err(storage_error)
+        })
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.inner.remove_file(path).await?;
+
+            let path = property_path(path);
+            Property::delete_within(&self.db, &path)
+                .await
+                .map_err(storage_error)?;
+            FileUsage::delete_within(&self.db, &path)
+                .await
+                .map_err(storage_error)
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.inner.rename(from, to).await?;
+
+            let (from, to) = (property_path(from), property_path(to));
+            Property::move_within(&self.db, &from, &to)
+                .await
+                .map_err(storage_error)?;
+            FileUsage::move_within(&self.db, &from, &to)
+                .await
+                .map_err(storage_error)?;
+            FileVersion::move_within(&self.db, &from, &to)
+                .await
+                .map_err(storage_error)
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            self.inner.copy(from, to).await?;
+
+            // The copy belongs to whoever made it
+            let meta = self.inner.metadata(to).await?;
+            FileUsage::record(&self.db, &property_path(to), &self.owner, meta.len() as i64)
+                .await
+                .map_err(storage_error)
+        })
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.inner.set_accessed(path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        self.inner.set_modified(path, tm)
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        _path: &'a DavPath,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(future::ready(true))
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        Box::pin(async move {
+            let path = property_path(path);
+
+            let mut results = Vec::with_capacity(patch.len());
+            for (set, prop) in patch {
+                let namespace = prop.namespace.clone().unwrap_or_default();
+                if set {
+                    let property = Property {
+                        path: path.clone(),
+                        namespace,
+                        name: prop.name.clone(),
+                        prefix: prop.prefix.clone(),
+                        xml: prop.xml,
+                    };
+                    property.save(&self.db).await.map_err(storage_error)?;
+                } else {
+                    // Removing a property that does not exist is not an error
+                    Property::delete(&self.db, &path, &namespace, &prop.name)
+                        .await
+                        .map_err(storage_error)?;
+                }
+
+                let prop = DavProp { xml: None, ..prop };
+                results.push((StatusCode::OK, prop));
+            }
+
+            Ok(results)
+        })
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        Box::pin(async move {
+            let properties = Property::list(&self.db, &property_path(path))
+                .await
+                .map_err(storage_error)?;
+
+            let props = properties
+                .into_iter()
+                .map(|property| DavProp {
+                    name: property.name,
+                    prefix: property.prefix,
+                    namespace: Some(property.namespace).filter(|ns| !ns.is_empty()),
+                    xml: property.xml.filter(|_| do_content),
+                })
+                .collect();
+            Ok(props)
+        })
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let namespace = prop.namespace.unwrap_or_default();
+            let property = Property::get(&self.db, &property_path(path), &namespace, &prop.name)
+                .await
+                .map_err(storage_error)?;
+
+            property
+                .and_then(|property| property.xml)
+                .ok_or(FsError::NotFound)
+        })
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        match self.quota {
+            Some(quota) => Box::pin(future::ready(Ok((quota.used, Some(quota.limit))))),
+            None => self.inner.get_quota(),
+        }
+    }
+}
+
+/// Wraps a file being written to stop writing once the available space runs out
+#[derive(Debug)]
+struct LimitedFile {
+    inner: Box<dyn DavFile>,
+    /// The number of bytes that can still be written
+    remaining: u64,
+}
+
+impl LimitedFile {
+    /// Take the bytes from the remaining space, failing if there is not enough left
+    fn reserve(&mut self, bytes: usize) -> Result<(), FsError> {
+        self.remaining = self
+            .remaining
+            .checked_sub(bytes as u64)
+            .ok_or(FsError::InsufficientStorage)?;
+        Ok(())
+    }
+}
+
+impl DavFile for LimitedFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.inner.metadata()
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        match self.reserve(buf.remaining()) {
+            Ok(()) => self.inner.write_buf(buf),
+            Err(e) => Box::pin(future::ready(Err(e))),
+        }
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        match self.reserve(buf.len()) {
+            Ok(()) => self.inner.write_bytes(buf),
+            Err(e) => Box::pin(future::ready(Err(e))),
+        }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        self.inner.read_bytes(count)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.inner.seek(pos)
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.inner.flush()
+    }
+}
+
+/// Get the key properties, usage, and versions are stored under for a path
+fn property_path(path: &DavPath) -> String {
+    path.as_rel_ospath().to_string_lossy().into_owned()
+}
+
+/// Report a failure to keep the database in sync with the file system
+fn storage_error(e: sqlx::Error) -> FsError {
+    error!(error = %e, "failed to update the database");
+    FsError::GeneralFailure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LimitedFile;
+    use bytes::{Buf, Bytes};
+    use dav_server::fs::{DavFile, DavMetaData, FsError, FsFuture};
+    use std::{future, io::SeekFrom};
+
+    /// A file that only counts the bytes written to it
+    #[derive(Debug, Default)]
+    struct CountingFile {
+        written: usize,
+    }
+
+    impl DavFile for CountingFile {
+        fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+            Box::pin(future::ready(Err(FsError::NotImplemented)))
+        }
+
+        fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+            self.written += buf.remaining();
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+            self.written += buf.len();
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn read_bytes(&mut self, _count: usize) -> FsFuture<'_, Bytes> {
+            Box::pin(future::ready(Err(FsError::NotImplemented)))
+        }
+
+        fn seek(&mut self, _pos: SeekFrom) -> FsFuture<'_, u64> {
+            Box::pin(future::ready(Err(FsError::NotImplemented)))
+        }
+
+        fn flush(&mut self) -> FsFuture<'_, ()> {
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    fn limited(remaining: u64) -> LimitedFile {
+        LimitedFile {
+            inner: Box::new(CountingFile::default()),
+            remaining,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_within_limit() {
+        let mut file = limited(10);
+        file.write_bytes(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        file.write_buf(Box::new(Bytes::from_static(b"world")))
+            .await
+            .unwrap();
+
+        assert_eq!(file.remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn stops_writing_past_limit() {
+        let mut file = limited(8);
+        file.write_bytes(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+
+        let result = file.write_bytes(Bytes::from_static(b"world")).await;
+        assert!(matches!(result, Err(FsError::InsufficientStorage)));
+        let result = file.write_buf(Box::new(Bytes::from_static(b"!!!!"))).await;
+        assert!(matches!(result, Err(FsError::InsufficientStorage)));
+
+        // Nothing past the limit reaches the underlying file
+        assert_eq!(format!("{:?}", file.inner), "CountingFile { written: 5 }");
+    }
+}
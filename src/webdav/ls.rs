@@ -0,0 +1,499 @@
+use crate::database::Lock;
+use dav_server::{
+    davpath::DavPath,
+    ls::{DavLock, DavLockSystem},
+};
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    time::{Duration, SystemTime},
+};
+use time::OffsetDateTime;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task,
+};
+use tracing::error;
+use uuid::Uuid;
+use xmltree::{Element, EmitterConfig};
+
+/// A lock system persisted in the database, so locks survive restarts and are shared between
+/// servers. Lock checks mirror those of the in-memory lock system provided by `dav_server`.
+///
+/// The lock system interface is synchronous, so each query blocks the worker thread it runs on.
+/// It must only be used from within the multi-threaded runtime.
+#[derive(Clone, Debug)]
+pub struct PgLs {
+    db: PgPool,
+}
+
+impl PgLs {
+    /// Create a new lock system using the database
+    pub fn new(db: PgPool) -> Box<PgLs> {
+        Box::new(PgLs { db })
+    }
+}
+
+impl DavLockSystem for PgLs {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> Result<DavLock, DavLock> {
+        let target = relative_path(path);
+        let lock = Lock {
+            token: Uuid::new_v4().urn().to_string(),
+            path: target.clone(),
+            href: path.with_prefix().as_url_string(),
+            principal: principal.map(String::from),
+            owner: owner.and_then(serialize_owner),
+            shared,
+            deep,
+            timeout_secs: timeout.map(|t| t.as_secs() as i64),
+            timeout_at: timeout.map(|t| OffsetDateTime::from(SystemTime::now() + t)),
+            created_at: OffsetDateTime::now_utc(),
+        };
+
+        let result = block_on(Lock::acquire(&self.db, lock.clone(), |related| {
+            conflicts_to_path(related, &target, None, true, &[], shared).or_else(|| {
+                deep.then(|| conflicts_from_path(related, &target, None, true, &[], shared))
+                    .flatten()
+            })
+        }));
+
+        match result {
+            Ok(Ok(lock)) => Ok(into_dav_lock(lock, path)),
+            Ok(Err(conflict)) => Err(into_dav_lock(conflict, path)),
+            Err(e) => {
+                error!(error = %e, "failed to acquire lock");
+                Err(into_dav_lock(lock, path))
+            }
+        }
+    }
+
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        let target = relative_path(path);
+        let result = block_on(async {
+            let related = Lock::related(&self.db, &target).await?;
+            match find_on_path(&related, &target, token) {
+                Some(lock) => Lock::delete(&self.db, &lock.token).await.map(|_| true),
+                None => Ok(false),
+            }
+        });
+
+        match result {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(()),
+            Err(e) => {
+                error!(error = %e, "failed to release lock");
+                Err(())
+            }
+        }
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, ()> {
+        let target = relative_path(path);
+        let timeout_secs = timeout.map(|t| t.as_secs() as i64);
+        let timeout_at = timeout.map(|t| OffsetDateTime::from(SystemTime::now() + t));
+
+        let result = block_on(async {
+            let related = Lock::related(&self.db, &target).await?;
+            match find_on_path(&related, &target, token) {
+                Some(lock) => Lock::refresh(&self.db, &lock.token, timeout_secs, timeout_at).await,
+                None => Ok(None),
+            }
+        });
+
+        match result {
+            Ok(Some(lock)) => Ok(into_dav_lock(lock, path)),
+            Ok(None) => Err(()),
+            Err(e) => {
+                error!(error = %e, "failed to refresh lock");
+                Err(())
+            }
+        }
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock> {
+        let target = relative_path(path);
+        let related = match block_on(Lock::related(&self.db, &target)) {
+            Ok(related) => related,
+            Err(e) => {
+                // Without knowing what is locked, assume the worst
+                error!(error = %e, "failed to check locks");
+                return Err(unknown_lock(path));
+            }
+        };
+
+        let conflict = conflicts_to_path(
+            &related,
+            &target,
+            principal,
+            ignore_principal,
+            &submitted_tokens,
+            false,
+        )
+        .or_else(|| {
+            deep.then(|| {
+                conflicts_from_path(
+                    &related,
+                    &target,
+                    principal,
+                    ignore_principal,
+                    &submitted_tokens,
+                    false,
+                )
+            })
+            .flatten()
+        });
+
+        match conflict {
+            Some(lock) => Err(into_dav_lock(lock, path)),
+            None => Ok(()),
+        }
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        let target = relative_path(path);
+        match block_on(Lock::related(&self.db, &target)) {
+            Ok(related) => related
+                .into_iter()
+                .filter(|lock| is_within(&target, &lock.path))
+                .map(|lock| into_dav_lock(lock, path))
+                .collect(),
+            Err(e) => {
+                error!(error = %e, "failed to discover locks");
+                Vec::new()
+            }
+        }
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let target = relative_path(path);
+        block_on(Lock::delete_within(&self.db, &target)).map_err(|e| {
+            error!(error = %e, "failed to delete locks");
+        })
+    }
+}
+
+/// Find a lock on the path, or any of its parents, that prevents it from being accessed
+fn conflicts_to_path(
+    related: &[Lock],
+    path: &str,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &[&str],
+    shared_ok: bool,
+) -> Option<Lock> {
+    let mut holds_lock = false;
+    let mut first_shared = None;
+
+    for lock in related.iter().filter(|lock| is_within(path, &lock.path)) {
+        // Locks on parents only apply to their children if they are deep
+        if lock.path != path && !lock.deep {
+            continue;
+        }
+
+        if holds(lock, principal, ignore_principal, submitted_tokens) {
+            holds_lock = true;
+        } else if !lock.shared {
+            return Some(lock.clone());
+        } else if !shared_ok {
+            first_shared.get_or_insert(lock);
+        }
+    }
+
+    match holds_lock {
+        true => None,
+        false => first_shared.cloned(),
+    }
+}
+
+/// Find a lock on the path, or any of its children, that prevents it from being accessed
+fn conflicts_from_path(
+    related: &[Lock],
+    path: &str,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &[&str],
+    shared_ok: bool,
+) -> Option<Lock> {
+    related
+        .iter()
+        .filter(|lock| is_within(&lock.path, path))
+        .find(|lock| {
+            (!lock.shared || !shared_ok)
+                && !holds(lock, principal, ignore_principal, submitted_tokens)
+        })
+        .cloned()
+}
+
+/// Find the lock with the token on the path or any of its parents
+fn find_on_path<'l>(related: &'l [Lock], path: &str, token: &str) -> Option<&'l Lock> {
+    related
+        .iter()
+        .find(|lock| lock.token == token && is_within(path, &lock.path))
+}
+
+/// Check if the request holds the lock
+fn holds(
+    lock: &Lock,
+    principal: Option<&str>,
+    ignore_principal: bool,
+    submitted_tokens: &[&str],
+) -> bool {
+    submitted_tokens.contains(&lock.token.as_str())
+        && (ignore_principal || principal == lock.principal.as_deref())
+}
+
+/// Check if the path is the same as, or a child of, the parent
+fn is_within(path: &str, parent: &str) -> bool {
+    parent.is_empty()
+        || path == parent
+        || path
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Get the path relative to the served directory, without leading or trailing slashes
+fn relative_path(path: &DavPath) -> String {
+    String::from_utf8_lossy(path.as_bytes())
+        .trim_matches('/')
+        .to_string()
+}
+
+/// Convert a stored lock into one the WebDAV handler understands. The URL prefix is taken from the
+/// path of the current request.
+fn into_dav_lock(lock: Lock, request: &DavPath) -> DavLock {
+    let path = DavPath::new(&lock.href)
+        .and_then(|mut path| path.set_prefix(request.prefix()).map(|_| path))
+        .unwrap_or_else(|_| request.clone());
+
+    DavLock {
+        token: lock.token,
+        path,
+        principal: lock.principal,
+        owner: lock
+            .owner
+            .and_then(|owner| Element::parse(owner.as_bytes()).ok()),
+        timeout_at: lock.timeout_at.map(SystemTime::from),
+        timeout: lock.timeout_secs.map(|t| Duration::from_secs(t as u64)),
+        shared: lock.shared,
+        deep: lock.deep,
+    }
+}
+
+/// A placeholder for when the locks on a path could not be determined
+fn unknown_lock(path: &DavPath) -> DavLock {
+    DavLock {
+        token: String::new(),
+        path: path.clone(),
+        principal: None,
+        owner: None,
+        timeout_at: None,
+        timeout: None,
+        shared: false,
+        deep: false,
+    }
+}
+
+/// Serialize the owner information provided by the client
+fn serialize_owner(owner: &Element) -> Option<String> {
+    let config = EmitterConfig::new().write_document_declaration(false);
+
+    let mut buffer = Vec::new();
+    owner.write_with_config(&mut buffer, config).ok()?;
+    String::from_utf8(buffer).ok()
+}
+
+/// Run a database query from the synchronous lock system interface. `block_in_place` panics on a
+/// current-thread runtime, so this fails loudly there rather than deep within tokio.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let handle = Handle::current();
+    assert!(
+        handle.runtime_flavor() == RuntimeFlavor::MultiThread,
+        "the WebDAV lock system requires the multi-threaded runtime"
+    );
+    task::block_in_place(|| handle.block_on(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_on, conflicts_from_path, conflicts_to_path, holds, is_within};
+    use crate::database::Lock;
+    use time::OffsetDateTime;
+
+    fn lock(token: &str, path: &str, shared: bool, deep: bool) -> Lock {
+        Lock {
+            token: token.to_string(),
+            path: path.to_string(),
+            href: format!("/dav/{path}"),
+            principal: Some(String::from("alice")),
+            owner: None,
+            shared,
+            deep,
+            timeout_secs: None,
+            timeout_at: None,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Find the token of the lock conflicting with `path` or its parents
+    fn to_path(related: &[Lock], path: &str, tokens: &[&str], shared_ok: bool) -> Option<String> {
+        conflicts_to_path(related, path, Some("alice"), false, tokens, shared_ok).map(|l| l.token)
+    }
+
+    /// Find the token of the lock conflicting with `path` or its children
+    fn from_path(related: &[Lock], path: &str, tokens: &[&str], shared_ok: bool) -> Option<String> {
+        conflicts_from_path(related, path, Some("alice"), false, tokens, shared_ok).map(|l| l.token)
+    }
+
+    #[test]
+    fn within() {
+        assert!(is_within("a", "a"));
+        assert!(is_within("a/b/c", "a"));
+        assert!(is_within("a/b", "a/b"));
+        assert!(is_within("a", ""));
+        assert!(is_within("", ""));
+        assert!(!is_within("ab", "a"));
+        assert!(!is_within("a", "a/b"));
+        assert!(!is_within("", "a"));
+    }
+
+    #[test]
+    fn holds_lock() {
+        let lock = lock("t1", "a", false, false);
+
+        assert!(holds(&lock, Some("alice"), false, &["t1"]));
+        assert!(holds(&lock, Some("alice"), false, &["t0", "t1"]));
+        assert!(!holds(&lock, Some("alice"), false, &[]));
+        assert!(!holds(&lock, Some("alice"), false, &["t2"]));
+
+        // The token alone is not enough unless the principal is ignored
+        assert!(!holds(&lock, Some("bob"), false, &["t1"]));
+        assert!(!holds(&lock, None, false, &["t1"]));
+        assert!(holds(&lock, Some("bob"), true, &["t1"]));
+    }
+
+    #[test]
+    fn exclusive_lock_conflicts() {
+        let related = [lock("t1", "a", false, false)];
+
+        assert_eq!(to_path(&related, "a", &[], false), Some(String::from("t1")));
+        assert_eq!(to_path(&related, "a", &[], true), Some(String::from("t1")));
+        assert_eq!(to_path(&related, "a", &["t1"], false), None);
+        assert_eq!(to_path(&related, "b", &[], false), None);
+    }
+
+    #[test]
+    fn shared_lock_conflicts_with_exclusive() {
+        let related = [lock("t1", "a", true, false), lock("t2", "a", true, false)];
+
+        assert_eq!(to_path(&related, "a", &[], true), None);
+        assert_eq!(to_path(&related, "a", &[], false), Some(String::from("t1")));
+
+        // Holding any of the shared locks is enough
+        assert_eq!(to_path(&related, "a", &["t2"], false), None);
+    }
+
+    #[test]
+    fn shared_lock_does_not_hide_exclusive() {
+        let related = [lock("t1", "a", true, true), lock("t2", "a/b", false, false)];
+
+        assert_eq!(
+            to_path(&related, "a/b", &["t1"], true),
+            Some(String::from("t2"))
+        );
+    }
+
+    #[test]
+    fn deep_lock_covers_descendants() {
+        let related = [lock("t1", "a", false, true)];
+
+        assert_eq!(
+            to_path(&related, "a/b/c", &[], false),
+            Some(String::from("t1"))
+        );
+        assert_eq!(to_path(&related, "a/b/c", &["t1"], false), None);
+        assert_eq!(to_path(&related, "ab", &[], false), None);
+    }
+
+    #[test]
+    fn shallow_lock_only_covers_itself() {
+        let related = [lock("t1", "a", false, false)];
+
+        assert_eq!(to_path(&related, "a/b", &[], false), None);
+        assert_eq!(from_path(&related, "a/b", &[], false), None);
+    }
+
+    #[test]
+    fn root_lock_covers_everything() {
+        let related = [lock("t1", "", false, true)];
+
+        assert_eq!(
+            to_path(&related, "a/b", &[], false),
+            Some(String::from("t1"))
+        );
+    }
+
+    #[test]
+    fn descendant_locks_conflict() {
+        let related = [
+            lock("t1", "a/b/c", false, false),
+            lock("t2", "a/d", true, false),
+        ];
+
+        assert_eq!(
+            from_path(&related, "a", &[], false),
+            Some(String::from("t1"))
+        );
+        assert_eq!(
+            from_path(&related, "a", &["t1"], false),
+            Some(String::from("t2"))
+        );
+        assert_eq!(from_path(&related, "a", &["t1"], true), None);
+        assert_eq!(from_path(&related, "a/d", &[], true), None);
+        assert_eq!(
+            from_path(&related, "", &[], false),
+            Some(String::from("t1"))
+        );
+    }
+
+    #[test]
+    fn ancestor_locks_are_not_descendants() {
+        let related = [lock("t1", "a", false, true)];
+
+        assert_eq!(from_path(&related, "a/b", &[], false), None);
+        assert_eq!(
+            from_path(&related, "a", &[], false),
+            Some(String::from("t1"))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocks_on_multi_threaded_runtime() {
+        assert_eq!(block_on(async { 1 }), 1);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "multi-threaded runtime")]
+    async fn rejects_current_thread_runtime() {
+        block_on(async {});
+    }
+}
@@ -0,0 +1,586 @@
+use crate::{
+    config::Config,
+    database::{AccessToken, Capability, Lock, Property, User},
+    error::{Error, Result},
+    security::{check_permissions, check_token_scope, sanitize_path, PermissionCache, Visibility},
+    trash, versions,
+};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode, Uri},
+    response::Response,
+    Extension,
+};
+use dav_server::{
+    body::Body as DavBody, davpath::DavPath, localfs::LocalFs, ls::DavLockSystem, DavConfig,
+    DavHandler, DavMethod,
+};
+use percent_encoding::percent_decode_str;
+use sqlx::PgPool;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+mod fs;
+mod ls;
+mod quota;
+
+pub use quota::{disk_usage, reconcile as reconcile_folder_usage};
+
+use fs::FilteredFs;
+use ls::PgLs;
+use quota::Quota;
+
+/// Build the WebDAV handler the server should use. The file system is attached to each request
+/// so it can be tailored to the current user, while locks are persisted in the database.
+pub fn dav_handler(db: PgPool) -> DavHandler {
+    let ls = PgLs::new(db);
+
+    DavHandler::builder()
+        .strip_prefix("/dav")
+        .locksystem(ls)
+        .build_handler()
+}
+
+/// Build the file system interface for the current user
+fn filesystem(
+    base: &Path,
+    visibility: Visibility,
+    db: PgPool,
+    user: &User,
+    quota: Option<Quota>,
+    write_limit: Option<u64>,
+) -> Box<FilteredFs> {
+    let fs = LocalFs::new(base, false, false, false);
+    FilteredFs::new(
+        fs,
+        visibility,
+        db,
+        user.username.clone(),
+        quota,
+        write_limit,
+    )
+}
+
+/// Handle WebDAV requests
+pub async fn handler(
+    Extension(webdav): Extension<DavHandler>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(user): Extension<User>,
+    Extension(db): Extension<PgPool>,
+    Extension(cache): Extension<Arc<PermissionCache>>,
+    access_token: Option<Extension<AccessToken>>,
+    req: Request<Body>,
+) -> Result<Response<DavBody>> {
+    let access_token = access_token.map(|Extension(access_token)| access_token);
+    let path = request_path(req.uri())?;
+    let method: DavMethod = req.method().try_into()?;
+
+    // Check the user's permissions
+    let exists = tokio::fs::try_exists(config.path.join(&path)).await?;
+    let required = required_permission(method, exists);
+    authorize(&db, &cache, &user, access_token.as_ref(), &path, required).await?;
+
+    // Copying and moving also write to the destination, so the user must be allowed to create
+    // the destination and, if it will be overwritten, delete what is already there
+    let destination = match method {
+        DavMethod::Copy | DavMethod::Move => Some(destination_path(req.headers())?),
+        _ => None,
+    };
+    let destination_exists = match &destination {
+        Some(destination) => tokio::fs::try_exists(config.path.join(destination)).await?,
+        None => false,
+    };
+    if let Some(destination) = &destination {
+        let required = required_permission(DavMethod::Put, false);
+        authorize(
+            &db,
+            &cache,
+            &user,
+            access_token.as_ref(),
+            destination,
+            required,
+        )
+        .await?;
+
+        if overwrite(req.headers()) && destination_exists {
+            let required = required_permission(DavMethod::Delete, true);
+            authorize(
+                &db,
+                &cache,
+                &user,
+                access_token.as_ref(),
+                destination,
+                required,
+            )
+            .await?;
+        }
+    }
+
+    // Writes must stay within the user's and the folder's quotas
+    let transfer = quota::ensure_available(
+        &db,
+        &config.path,
+        &user,
+        method,
+        &path,
+        destination.as_deref(),
+        req.headers(),
+    )
+    .await?;
+
+    // Deleted items are moved to the trash so they can be restored later
+    if let DavMethod::Delete = method {
+        if !is_unlocked(&db, &user, req.uri(), req.headers())? {
+            return Ok(empty_response(StatusCode::LOCKED));
+        }
+
+        trash::move_to_trash(&db, &config.path, &user, &path).await?;
+        Lock::delete_within(&db, &path.to_string_lossy()).await?;
+        return Ok(empty_response(StatusCode::NO_CONTENT));
+    }
+
+    // The same goes for anything that would be overwritten by a copy or move. It is only trashed
+    // once the copy or move is known to be possible, and is put back if it fails anyway.
+    let mut replaced = None;
+    if let Some(destination) = &destination {
+        let replacing = overwrite(req.headers()) && *destination != path && destination_exists;
+        if replacing {
+            if !exists {
+                return Err(Error::NotFound);
+            }
+
+            let source_unlocked = !matches!(method, DavMethod::Move)
+                || is_unlocked(&db, &user, req.uri(), req.headers())?;
+            let destination_unlocked =
+                is_unlocked(&db, &user, &destination_uri(req.headers())?, req.headers())?;
+            if !source_unlocked || !destination_unlocked {
+                return Ok(empty_response(StatusCode::LOCKED));
+            }
+
+            replaced = Some(trash::move_to_trash(&db, &config.path, &user, destination).await?);
+        }
+    }
+
+    // Only report the quota when it could be requested, as finding the usage can be expensive
+    let quota = match method {
+        DavMethod::PropFind => quota::effective(&db, &user, &path).await?,
+        _ => None,
+    };
+
+    // Hide any entries the user cannot see from directory listings
+    let visibility = Visibility::load(&db, &cache, &user).await?;
+    let dav_config = DavConfig::new()
+        .filesystem(filesystem(
+            &config.path,
+            visibility,
+            db.clone(),
+            &user,
+            quota,
+            transfer.limit,
+        ))
+        .principal(user.username.clone());
+
+    // Keep the previous content of files being overwritten. If the file is locked the upload will
+    // be rejected, so there is nothing to keep.
+    let version = match method {
+        DavMethod::Put if is_unlocked(&db, &user, req.uri(), req.headers())? => {
+            versions::snapshot(&db, &config, &user, &path).await?
+        }
+        _ => None,
+    };
+
+    let response = webdav.handle_with(dav_config, req).await;
+
+    // Put back whatever the copy or move was going to replace if it failed, otherwise its locks
+    // went with it
+    if let (Some(item), Some(destination)) = (&replaced, &destination) {
+        match response.status().is_success() {
+            true => Lock::delete_within(&db, &destination.to_string_lossy()).await?,
+            false => trash::restore_replaced(&db, &config.path, item).await?,
+        }
+    }
+
+    // A failed upload, such as one that ran out of space or was interrupted, can leave part of the
+    // file behind, so put back what was there before. The snapshot is only dropped once the file
+    // has its previous content again. Without one, only what is known to be partial is removed.
+    if matches!(method, DavMethod::Put) && !response.status().is_success() {
+        let out_of_space = response.status() == StatusCode::INSUFFICIENT_STORAGE;
+        match &version {
+            Some(version) => versions::revert(&config.path, version).await?,
+            None if !exists || out_of_space => {
+                quota::discard_upload(&db, &config.path, &path, transfer).await?
+            }
+            None => {}
+        }
+    }
+
+    if let Some(version) = version {
+        match response.status().is_success() {
+            true => versions::prune(&db, &config, &path).await?,
+            false => versions::remove(&db, &config.path, &version).await?,
+        }
+    }
+
+    // Count what was written against the user's and the folder's quotas
+    if let StatusCode::CREATED | StatusCode::NO_CONTENT = response.status() {
+        quota::record(
+            &db,
+            &config.path,
+            &user,
+            method,
+            &path,
+            destination.as_deref(),
+            transfer,
+        )
+        .await?;
+    }
+
+    // Directories are copied entry by entry, so the dead properties are copied once the whole
+    // copy has succeeded
+    if let (DavMethod::Copy, Some(destination)) = (method, &destination) {
+        if let StatusCode::CREATED | StatusCode::NO_CONTENT = response.status() {
+            Property::copy_within(&db, &path.to_string_lossy(), &destination.to_string_lossy())
+                .await?;
+        }
+    }
+
+    Ok(response)
+}
+
+/// Check the user, and the access token they authenticated with, have the required capability for
+/// the path
+async fn authorize(
+    db: &PgPool,
+    cache: &PermissionCache,
+    user: &User,
+    access_token: Option<&AccessToken>,
+    path: &Path,
+    required: Capability,
+) -> Result<()> {
+    check_permissions(db, cache, user, path, required).await?;
+    if let Some(access_token) = access_token {
+        check_token_scope(access_token, path, required)?;
+    }
+
+    Ok(())
+}
+
+/// Get the sanitized path within the served directory that the URI refers to
+fn request_path(uri: &Uri) -> Result<PathBuf> {
+    let raw_path = uri.path().strip_prefix("/dav").ok_or(Error::BadRequest)?;
+    let decoded_path = percent_decode_str(raw_path)
+        .decode_utf8_lossy()
+        .into_owned();
+
+    sanitize_path(decoded_path.into())
+}
+
+/// Get the sanitized path from the Destination header of a COPY or MOVE request
+fn destination_path(headers: &HeaderMap) -> Result<PathBuf> {
+    request_path(&destination_uri(headers)?)
+}
+
+/// Get the URI from the Destination header of a COPY or MOVE request
+fn destination_uri(headers: &HeaderMap) -> Result<Uri> {
+    let destination = headers
+        .get("destination")
+        .ok_or(Error::BadRequest)?
+        .to_str()
+        .map_err(|_| Error::BadRequest)?;
+
+    destination.parse::<Uri>().map_err(|_| Error::BadRequest)
+}
+
+/// Check the resource at the URI is not locked, or that the request holds the lock on it
+fn is_unlocked(db: &PgPool, user: &User, uri: &Uri, headers: &HeaderMap) -> Result<bool> {
+    let mut path = DavPath::from_uri(uri).map_err(|_| Error::BadRequest)?;
+    path.set_prefix("/dav").map_err(|_| Error::BadRequest)?;
+
+    let tokens = lock_tokens(headers);
+    let tokens = tokens.iter().map(String::as_str).collect();
+
+    let ls = PgLs::new(db.clone());
+    Ok(ls
+        .check(&path, Some(&user.username), false, true, tokens)
+        .is_ok())
+}
+
+/// Get the lock tokens submitted in the If header. Only the tokens are needed, so the conditions
+/// they are part of are not evaluated.
+fn lock_tokens(headers: &HeaderMap) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    for value in headers.get_all("if") {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+
+        // Tokens are enclosed in angle brackets within a list of conditions, while resource tags
+        // are enclosed in angle brackets outside of them. Entity tags are enclosed in square
+        // brackets and skipped entirely, as they may contain angle brackets.
+        let mut in_list = false;
+        let mut rest = value;
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '(' => in_list = true,
+                ')' => in_list = false,
+                '<' if in_list => {
+                    if let Some(end) = rest.find('>') {
+                        tokens.push(rest[1..end].to_string());
+                        rest = &rest[end..];
+                    }
+                }
+                '[' if in_list => {
+                    if let Some(end) = rest.find(']') {
+                        rest = &rest[end..];
+                    }
+                }
+                _ => {}
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    tokens
+}
+
+/// Build a response without a body
+fn empty_response(status: StatusCode) -> Response<DavBody> {
+    let mut response = Response::new(DavBody::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Whether the request allows overwriting an existing destination. Per RFC 4918, a missing
+/// Overwrite header is treated as `T`.
+fn overwrite(headers: &HeaderMap) -> bool {
+    match headers.get("overwrite") {
+        Some(value) => !value.as_bytes().eq_ignore_ascii_case(b"F"),
+        None => true,
+    }
+}
+
+/// Get the capability required for the requested method, depending on whether the requested
+/// resource already exists
+fn required_permission(method: DavMethod, exists: bool) -> Capability {
+    use DavMethod::*;
+
+    match method {
+        Options | PropFind => Capability::List,
+        Get | Head | Copy => Capability::Read,
+        MkCol => Capability::Create,
+        Put if !exists => Capability::Create,
+        Put | Patch | PropPatch => Capability::Overwrite,
+        Delete | Move => Capability::Delete,
+        Lock | Unlock => Capability::Lock,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dav_handler, handler, lock_tokens};
+    use crate::{
+        config::Config,
+        database::{self, FileVersion, User},
+        security::PermissionCache,
+        versions::VERSIONS_DIR,
+    };
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+        Extension,
+    };
+    use bytes::Bytes;
+    use sqlx::PgPool;
+    use std::{path::PathBuf, sync::Arc};
+    use tokio::fs;
+    use uuid::Uuid;
+
+    struct Fixture {
+        db: PgPool,
+        config: Arc<Config>,
+        user: User,
+        /// A file within a folder only used by this test, as versions are recorded by path
+        file: String,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let username = format!("webdav-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &username, "Uploader")
+                .await
+                .unwrap();
+
+            let path = std::env::temp_dir().join(&username);
+            fs::create_dir_all(path.join(&username)).await.unwrap();
+            let config = Arc::new(Config {
+                path,
+                ..Config::default()
+            });
+
+            let file = format!("{username}/notes.txt");
+            Some(Fixture {
+                db,
+                config,
+                user,
+                file,
+            })
+        }
+
+        fn path(&self) -> PathBuf {
+            self.config.path.join(&self.file)
+        }
+
+        /// Upload a file, optionally cutting off the body after what was sent, as happens when the
+        /// client goes away
+        async fn put(&self, contents: &'static [u8], interrupt: bool) -> StatusCode {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                let _ = sender.send_data(Bytes::from_static(contents)).await;
+                if interrupt {
+                    sender.abort();
+                }
+            });
+
+            let length = if interrupt { 1024 } else { contents.len() };
+            let req = Request::put(format!("/dav/{}", self.file))
+                .header(header::CONTENT_LENGTH, length)
+                .body(body)
+                .unwrap();
+
+            let response = handler(
+                Extension(dav_handler(self.db.clone())),
+                Extension(self.config.clone()),
+                Extension(self.user.clone()),
+                Extension(self.db.clone()),
+                Extension(PermissionCache::new()),
+                None,
+                req,
+            )
+            .await
+            .unwrap();
+            response.status()
+        }
+
+        async fn versions(&self) -> Vec<FileVersion> {
+            FileVersion::list(&self.db, &self.file).await.unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.config.path);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn put_keeps_previous_version() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        fs::write(fixture.path(), "original").await.unwrap();
+
+        let status = fixture.put(b"updated", false).await;
+        assert!(status.is_success());
+        let contents = fs::read_to_string(fixture.path()).await;
+        assert_eq!(contents.unwrap(), "updated");
+        assert_eq!(fixture.versions().await.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn interrupted_put_keeps_previous_content() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        fs::write(fixture.path(), "original").await.unwrap();
+
+        let status = fixture.put(b"partial", true).await;
+        assert!(!status.is_success());
+        let contents = fs::read_to_string(fixture.path()).await;
+        assert_eq!(contents.unwrap(), "original");
+
+        // The content never changed, so there is no version to keep
+        assert!(fixture.versions().await.is_empty());
+        let mut stored = fs::read_dir(fixture.config.path.join(VERSIONS_DIR))
+            .await
+            .unwrap();
+        assert!(stored.next_entry().await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn interrupted_put_of_new_file_is_discarded() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+
+        let status = fixture.put(b"partial", true).await;
+        assert!(!status.is_success());
+        let exists = fs::try_exists(fixture.path()).await;
+        assert!(!exists.unwrap());
+    }
+
+    fn tokens(values: &[&'static str]) -> Vec<String> {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("if", HeaderValue::from_static(value));
+        }
+
+        lock_tokens(&headers)
+    }
+
+    #[test]
+    fn no_header() {
+        assert!(tokens(&[]).is_empty());
+    }
+
+    #[test]
+    fn untagged_list() {
+        assert_eq!(
+            tokens(&["(<opaquelocktoken:a>)"]),
+            vec!["opaquelocktoken:a"]
+        );
+    }
+
+    #[test]
+    fn tagged_list() {
+        let found = tokens(&["<http://localhost/dav/file.txt> (<opaquelocktoken:a>)"]);
+        assert_eq!(found, vec!["opaquelocktoken:a"]);
+    }
+
+    #[test]
+    fn negated_token() {
+        // Like dav-server, tokens are collected whether or not the condition is negated
+        assert_eq!(
+            tokens(&["(Not <opaquelocktoken:a>)"]),
+            vec!["opaquelocktoken:a"]
+        );
+    }
+
+    #[test]
+    fn entity_tags() {
+        let found = tokens(&[r#"(<opaquelocktoken:a> ["abc<def>"]) ([W/"123"])"#]);
+        assert_eq!(found, vec!["opaquelocktoken:a"]);
+    }
+
+    #[test]
+    fn several_tokens() {
+        let found = tokens(&[
+            "<http://localhost/dav/a> (<opaquelocktoken:a>) <http://localhost/dav/b> (<opaquelocktoken:b> <opaquelocktoken:c>)",
+            "(<opaquelocktoken:d>) (Not <DAV:no-lock>)",
+        ]);
+        assert_eq!(
+            found,
+            vec![
+                "opaquelocktoken:a",
+                "opaquelocktoken:b",
+                "opaquelocktoken:c",
+                "opaquelocktoken:d",
+                "DAV:no-lock",
+            ]
+        );
+    }
+}
@@ -0,0 +1,622 @@
+use crate::{
+    config::Config,
+    database::{FileUsage, FolderQuota, Property, User},
+    error::{Error, Result},
+};
+use axum::http::{header, HeaderMap};
+use dav_server::DavMethod;
+use sqlx::PgPool;
+use std::{
+    io::{self, ErrorKind},
+    path::{Component, Path},
+    sync::Arc,
+};
+use tokio::fs;
+use tracing::{error, info, instrument};
+
+/// The number of bytes used and allowed by a quota
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl Quota {
+    /// The number of bytes that can still be stored
+    fn available(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+
+    /// The quota once the given number of bytes are freed
+    fn freeing(self, bytes: u64) -> Quota {
+        Quota {
+            used: self.used.saturating_sub(bytes),
+            ..self
+        }
+    }
+
+    /// Check if the incoming bytes fit within the quota. When the size is unknown, there must be at
+    /// least some space left, and the upload is stopped once it runs out.
+    fn fits(&self, incoming: Option<u64>) -> bool {
+        match incoming {
+            Some(incoming) => incoming <= self.available(),
+            None => self.available() > 0,
+        }
+    }
+}
+
+/// Get the most restrictive quota that applies to the user writing to the path
+pub async fn effective(db: &PgPool, user: &User, path: &Path) -> Result<Option<Quota>> {
+    let quotas = [for_user(db, user).await?, for_folder(db, path).await?];
+
+    Ok(quotas.into_iter().flatten().min_by_key(Quota::available))
+}
+
+/// The bytes a request writes, found while checking they fit within the quotas
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transfer {
+    /// The most bytes an upload can write before running out of space, if limited
+    pub limit: Option<u64>,
+    /// The size of the file replaced by an upload
+    pub replaced: u64,
+    /// The size of what is copied or moved between folders
+    pub incoming: u64,
+}
+
+/// Ensure the data written by the request fits within the user's quota and the quota of the
+/// destination folder
+pub async fn ensure_available(
+    db: &PgPool,
+    base: &Path,
+    user: &User,
+    method: DavMethod,
+    path: &Path,
+    destination: Option<&Path>,
+    headers: &HeaderMap,
+) -> Result<Transfer> {
+    let mut transfer = Transfer::default();
+
+    match (method, destination) {
+        (DavMethod::Put, _) => {
+            let incoming = content_length(headers);
+
+            // Overwriting a file frees the space it used, but only counts towards the user's
+            // quota if they uploaded it
+            transfer.replaced = match fs::metadata(base.join(path)).await {
+                Ok(meta) if meta.is_file() => meta.len(),
+                _ => 0,
+            };
+            let owned = match FileUsage::get(db, &path.to_string_lossy()).await? {
+                Some(usage) if usage.owner == user.username => transfer.replaced,
+                _ => 0,
+            };
+
+            let user_quota = for_user(db, user).await?.map(|q| q.freeing(owned));
+            ensure_fits(user_quota, incoming)?;
+
+            let folder_quota = for_folder(db, path).await?;
+            let folder_quota = folder_quota.map(|q| q.freeing(transfer.replaced));
+            ensure_fits(folder_quota, incoming)?;
+
+            // The declared size can't be trusted, so the upload is limited as it is written
+            transfer.limit = [user_quota, folder_quota]
+                .into_iter()
+                .flatten()
+                .map(|quota| quota.available())
+                .min();
+        }
+        (DavMethod::Copy, Some(destination)) => {
+            transfer.incoming = disk_usage(&base.join(path)).await?;
+            ensure_fits(for_user(db, user).await?, Some(transfer.incoming))?;
+            ensure_fits(for_folder(db, destination).await?, Some(transfer.incoming))?;
+        }
+        // Moving only changes the usage of folders, and only when moving between them
+        (DavMethod::Move, Some(destination)) if top_level(path) != top_level(destination) => {
+            transfer.incoming = disk_usage(&base.join(path)).await?;
+            ensure_fits(for_folder(db, destination).await?, Some(transfer.incoming))?;
+        }
+        _ => {}
+    }
+
+    Ok(transfer)
+}
+
+/// Update the usage of the user and folders once the request has succeeded
+pub async fn record(
+    db: &PgPool,
+    base: &Path,
+    user: &User,
+    method: DavMethod,
+    path: &Path,
+    destination: Option<&Path>,
+    transfer: Transfer,
+) -> Result<()> {
+    match (method, destination) {
+        (DavMethod::Put, _) => {
+            let meta = fs::metadata(base.join(path)).await?;
+            let path = path.to_string_lossy();
+            FileUsage::record(db, &path, &user.username, meta.len() as i64).await?;
+            FolderQuota::adjust_usage(db, &path, meta.len() as i64 - transfer.replaced as i64)
+                .await?;
+        }
+        (DavMethod::Copy, Some(destination)) => {
+            let destination = destination.to_string_lossy();
+            FolderQuota::adjust_usage(db, &destination, transfer.incoming as i64).await?;
+        }
+        (DavMethod::Move, Some(destination)) if top_level(path) != top_level(destination) => {
+            let (path, destination) = (path.to_string_lossy(), destination.to_string_lossy());
+            FolderQuota::adjust_usage(db, &path, -(transfer.incoming as i64)).await?;
+            FolderQuota::adjust_usage(db, &destination, transfer.incoming as i64).await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Remove what was written by an upload that failed, along with the usage of the file it replaced
+pub async fn discard_upload(
+    db: &PgPool,
+    base: &Path,
+    path: &Path,
+    transfer: Transfer,
+) -> Result<()> {
+    match fs::remove_file(base.join(path)).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let path = path.to_string_lossy();
+    Property::delete_within(db, &path).await?;
+    FileUsage::delete_within(db, &path).await?;
+    FolderQuota::adjust_usage(db, &path, -(transfer.replaced as i64)).await?;
+
+    Ok(())
+}
+
+/// Recount the usage of every folder with a quota from the disk, picking up any changes made
+/// outside of the server
+#[instrument(skip_all)]
+pub async fn reconcile(db: PgPool, config: Arc<Config>) {
+    let quotas = match FolderQuota::list(&db).await {
+        Ok(quotas) => quotas,
+        Err(e) => {
+            error!(error = %e, "failed to load folder quotas");
+            return;
+        }
+    };
+
+    for quota in &quotas {
+        let result = match disk_usage(&config.path.join(&quota.folder)).await {
+            Ok(used) => FolderQuota::set_usage(&db, &quota.folder, used as i64)
+                .await
+                .map_err(Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!(error = %e, folder = %quota.folder, "failed to recount folder usage");
+        }
+    }
+
+    if !quotas.is_empty() {
+        info!(count = quotas.len(), "recounted folder usage");
+    }
+}
+
+/// Get the quota for everything the user has stored
+async fn for_user(db: &PgPool, user: &User) -> Result<Option<Quota>> {
+    let limit = match user.quota {
+        Some(limit) => limit,
+        None => return Ok(None),
+    };
+
+    let used = FileUsage::total_for(db, &user.username).await?;
+    Ok(Some(Quota {
+        used: used as u64,
+        limit: limit as u64,
+    }))
+}
+
+/// Get the quota for the top-level folder containing the path
+async fn for_folder(db: &PgPool, path: &Path) -> Result<Option<Quota>> {
+    let folder = match top_level(path) {
+        Some(folder) => folder,
+        None => return Ok(None),
+    };
+    let quota = match FolderQuota::get(db, &folder.to_string_lossy()).await? {
+        Some(quota) => quota,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Quota {
+        used: quota.used.max(0) as u64,
+        limit: quota.quota as u64,
+    }))
+}
+
+/// Fail if the incoming bytes do not fit within the quota
+fn ensure_fits(quota: Option<Quota>, incoming: Option<u64>) -> Result<()> {
+    match quota {
+        Some(quota) if !quota.fits(incoming) => Err(Error::QuotaExceeded),
+        _ => Ok(()),
+    }
+}
+
+/// Get the top-level folder the path is within
+fn top_level(path: &Path) -> Option<&Path> {
+    match path.components().next() {
+        Some(Component::Normal(folder)) => Some(Path::new(folder)),
+        _ => None,
+    }
+}
+
+/// Get the size of the upload. Some clients, like macOS Finder, send the body in chunks and only
+/// provide the expected size in a separate header.
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .or_else(|| headers.get("x-expected-entity-length"))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Get the total size of the files within a path
+pub async fn disk_usage(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(path) = pending.pop() {
+        let meta = match fs::symlink_metadata(&path).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        if meta.is_dir() {
+            let mut entries = fs::read_dir(&path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                pending.push(entry.path());
+            }
+        } else {
+            total += meta.len();
+        }
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ensure_available, reconcile, record, Transfer};
+    use crate::{
+        config::Config,
+        database::{self, FileUsage, FolderQuota, User},
+        error::{Error, Result},
+    };
+    use axum::http::{header, HeaderMap};
+    use dav_server::DavMethod;
+    use sqlx::PgPool;
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, OnceLock},
+    };
+    use tokio::{
+        fs,
+        sync::{Mutex, MutexGuard},
+    };
+    use uuid::Uuid;
+
+    /// Reconciling recounts every folder quota, so tests using them must not run at the same time
+    static SERIAL: OnceLock<Mutex<()>> = OnceLock::new();
+
+    struct Fixture {
+        db: PgPool,
+        base: PathBuf,
+        user: User,
+        /// A top-level folder only used by this test
+        folder: String,
+        _serial: MutexGuard<'static, ()>,
+    }
+
+    impl Fixture {
+        async fn new() -> Option<Fixture> {
+            let db = database::connect_for_tests().await?;
+            let serial = SERIAL.get_or_init(Mutex::default).lock().await;
+
+            let folder = format!("quota-{}", Uuid::new_v4());
+            let user = User::create_if_not_exists(&db, &folder, "Uploader")
+                .await
+                .unwrap();
+            let base = std::env::temp_dir().join(&folder);
+            fs::create_dir_all(base.join(&folder)).await.unwrap();
+
+            Some(Fixture {
+                db,
+                base,
+                user,
+                folder,
+                _serial: serial,
+            })
+        }
+
+        /// Get the path of an item within the test's folder
+        fn path(&self, name: &str) -> PathBuf {
+            Path::new(&self.folder).join(name)
+        }
+
+        async fn write(&self, path: &Path, size: usize) {
+            let full_path = self.base.join(path);
+            fs::create_dir_all(full_path.parent().unwrap())
+                .await
+                .unwrap();
+            fs::write(full_path, vec![0; size]).await.unwrap();
+        }
+
+        async fn set_user_quota(&mut self, quota: i64) {
+            self.user.set_quota(&self.db, Some(quota)).await.unwrap();
+        }
+
+        async fn set_folder_quota(&self, folder: &str, quota: i64, used: i64) {
+            FolderQuota::set(&self.db, folder, quota, used)
+                .await
+                .unwrap();
+        }
+
+        async fn folder_usage(&self, folder: &str) -> i64 {
+            let quota = FolderQuota::get(&self.db, folder).await.unwrap();
+            quota.unwrap().used
+        }
+
+        async fn put(&self, path: &Path, length: Option<u64>) -> Result<Transfer> {
+            let mut headers = HeaderMap::new();
+            if let Some(length) = length {
+                headers.insert(header::CONTENT_LENGTH, length.into());
+            }
+
+            let (db, base, user) = (&self.db, &self.base, &self.user);
+            ensure_available(db, base, user, DavMethod::Put, path, None, &headers).await
+        }
+
+        async fn transfer(
+            &self,
+            method: DavMethod,
+            path: &Path,
+            destination: &Path,
+        ) -> Result<Transfer> {
+            let (db, base, user, headers) = (&self.db, &self.base, &self.user, &HeaderMap::new());
+            ensure_available(db, base, user, method, path, Some(destination), headers).await
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[tokio::test]
+    async fn put_within_user_quota() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.set_user_quota(10).await;
+        let path = fixture.path("file");
+
+        let transfer = fixture.put(&path, Some(10)).await.unwrap();
+        assert_eq!(transfer.limit, Some(10));
+        assert_eq!(transfer.replaced, 0);
+
+        let result = fixture.put(&path, Some(11)).await;
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+    }
+
+    #[tokio::test]
+    async fn put_of_unknown_size_needs_space() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.set_user_quota(10).await;
+        let path = fixture.path("file");
+
+        let transfer = fixture.put(&path, None).await.unwrap();
+        assert_eq!(transfer.limit, Some(10));
+
+        let other = fixture.path("other").to_string_lossy().into_owned();
+        FileUsage::record(&fixture.db, &other, &fixture.user.username, 10)
+            .await
+            .unwrap();
+        let result = fixture.put(&path, None).await;
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+    }
+
+    #[tokio::test]
+    async fn overwriting_frees_only_own_files() {
+        let Some(mut fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.set_user_quota(10).await;
+        let db = &fixture.db;
+
+        let own = fixture.path("own");
+        fixture.write(&own, 5).await;
+        FileUsage::record(db, &own.to_string_lossy(), &fixture.user.username, 5)
+            .await
+            .unwrap();
+
+        let theirs = fixture.path("theirs");
+        let other = User::create_if_not_exists(db, &format!("{}-other", fixture.folder), "Other")
+            .await
+            .unwrap();
+        fixture.write(&theirs, 8).await;
+        FileUsage::record(db, &theirs.to_string_lossy(), &other.username, 8)
+            .await
+            .unwrap();
+
+        let transfer = fixture.put(&own, Some(10)).await.unwrap();
+        assert_eq!(transfer.replaced, 5);
+        assert_eq!(transfer.limit, Some(10));
+
+        // Replacing someone else's file does not give the uploader any more space
+        let result = fixture.put(&theirs, Some(6)).await;
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+        let transfer = fixture.put(&theirs, Some(5)).await.unwrap();
+        assert_eq!(transfer.replaced, 8);
+        assert_eq!(transfer.limit, Some(5));
+    }
+
+    #[tokio::test]
+    async fn put_within_folder_quota() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let existing = fixture.path("existing");
+        fixture.write(&existing, 8).await;
+        fixture.set_folder_quota(&fixture.folder, 10, 8).await;
+
+        let result = fixture.put(&fixture.path("new"), Some(3)).await;
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+
+        // The folder always gets back the space of the file being replaced
+        let transfer = fixture.put(&existing, Some(10)).await.unwrap();
+        assert_eq!(transfer.replaced, 8);
+        assert_eq!(transfer.limit, Some(10));
+
+        // Other folders are not limited
+        let elsewhere = Path::new("elsewhere").join(&fixture.folder);
+        let transfer = fixture.put(&elsewhere, Some(100)).await.unwrap();
+        assert_eq!(transfer.limit, None);
+    }
+
+    #[tokio::test]
+    async fn copy_and_move_check_destination() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let source = fixture.path("source");
+        fixture.write(&source.join("a"), 4).await;
+        fixture.write(&source.join("b/c"), 2).await;
+
+        let destination = format!("{}-destination", fixture.folder);
+        fixture.set_folder_quota(&destination, 5, 0).await;
+        let target = Path::new(&destination).join("copy");
+
+        for method in [DavMethod::Copy, DavMethod::Move] {
+            let result = fixture.transfer(method, &source, &target).await;
+            assert!(matches!(result, Err(Error::QuotaExceeded)));
+        }
+
+        fixture.set_folder_quota(&destination, 6, 0).await;
+        let transfer = fixture
+            .transfer(DavMethod::Copy, &source, &target)
+            .await
+            .unwrap();
+        assert_eq!(transfer.incoming, 6);
+
+        // Moving within a folder does not change its usage
+        fixture.set_folder_quota(&fixture.folder, 0, 6).await;
+        let transfer = fixture
+            .transfer(DavMethod::Move, &source, &fixture.path("moved"))
+            .await
+            .unwrap();
+        assert_eq!(transfer.incoming, 0);
+        let result = fixture
+            .transfer(DavMethod::Copy, &source, &fixture.path("copied"))
+            .await;
+        assert!(matches!(result, Err(Error::QuotaExceeded)));
+    }
+
+    #[tokio::test]
+    async fn record_put() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let path = fixture.path("file");
+        fixture.write(&path, 3).await;
+        fixture.set_folder_quota(&fixture.folder, 100, 3).await;
+
+        let transfer = fixture.put(&path, Some(7)).await.unwrap();
+        fixture.write(&path, 7).await;
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+        record(db, base, user, DavMethod::Put, &path, None, transfer)
+            .await
+            .unwrap();
+
+        assert_eq!(fixture.folder_usage(&fixture.folder).await, 7);
+        let usage = FileUsage::get(db, &path.to_string_lossy()).await.unwrap();
+        assert_eq!(usage.unwrap().owner, user.username);
+        assert_eq!(FileUsage::total_for(db, &user.username).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn record_copy_and_move() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        let source = fixture.path("source");
+        fixture.write(&source, 6).await;
+        fixture.set_folder_quota(&fixture.folder, 100, 6).await;
+
+        let destination = format!("{}-destination", fixture.folder);
+        fixture.set_folder_quota(&destination, 100, 0).await;
+        let target = Path::new(&destination).join("target");
+
+        let (db, base, user) = (&fixture.db, &fixture.base, &fixture.user);
+        let transfer = fixture
+            .transfer(DavMethod::Copy, &source, &target)
+            .await
+            .unwrap();
+        record(
+            db,
+            base,
+            user,
+            DavMethod::Copy,
+            &source,
+            Some(&target),
+            transfer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fixture.folder_usage(&fixture.folder).await, 6);
+        assert_eq!(fixture.folder_usage(&destination).await, 6);
+
+        let transfer = fixture
+            .transfer(DavMethod::Move, &source, &target)
+            .await
+            .unwrap();
+        record(
+            db,
+            base,
+            user,
+            DavMethod::Move,
+            &source,
+            Some(&target),
+            transfer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fixture.folder_usage(&fixture.folder).await, 0);
+        assert_eq!(fixture.folder_usage(&destination).await, 12);
+    }
+
+    #[tokio::test]
+    async fn reconcile_recounts_from_disk() {
+        let Some(fixture) = Fixture::new().await else {
+            return;
+        };
+        fixture.write(&fixture.path("a"), 5).await;
+        fixture.write(&fixture.path("b/c"), 7).await;
+        fixture.set_folder_quota(&fixture.folder, 100, 999).await;
+
+        let missing = format!("{}-missing", fixture.folder);
+        fixture.set_folder_quota(&missing, 100, 50).await;
+
+        let config = Config {
+            path: fixture.base.clone(),
+            ..Config::default()
+        };
+        reconcile(fixture.db.clone(), Arc::new(config)).await;
+
+        assert_eq!(fixture.folder_usage(&fixture.folder).await, 12);
+        assert_eq!(fixture.folder_usage(&missing).await, 0);
+    }
+}